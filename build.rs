@@ -0,0 +1,97 @@
+//! Embeds the files in `static`, along with precompressed variants of those that compress well.
+
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+const STATIC_DIR: &str = "static";
+
+type Compress = fn(&[u8]) -> Vec<u8>;
+
+/// In order of preference.
+const ENCODINGS: [(&str, Compress); 3] = [("br", brotli), ("zstd", zstd), ("gzip", gzip)];
+
+fn main() {
+	println!("cargo:rerun-if-changed={STATIC_DIR}");
+
+	let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+	let manifest_dir = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap());
+
+	let mut paths: Vec<PathBuf> = std::fs::read_dir(manifest_dir.join(STATIC_DIR))
+		.unwrap()
+		.map(|entry| entry.unwrap().path())
+		.filter(|path| path.is_file())
+		.collect();
+	paths.sort();
+
+	let mut generated = "&[\n".to_owned();
+	for path in &paths {
+		let name = path.file_name().unwrap().to_str().unwrap();
+		let contents = std::fs::read(path).unwrap();
+		let mime = mime_guess::from_path(path).first_or_octet_stream();
+		let content_type = if mime.type_() == mime_guess::mime::TEXT {
+			format!("{mime}; charset=utf-8")
+		} else {
+			mime.to_string()
+		};
+
+		let mut encoded = String::new();
+		if is_compressible(&mime) {
+			for (encoding, compress) in ENCODINGS {
+				let compressed = compress(&contents);
+				// not worth the extra work for the client otherwise
+				if compressed.len() < contents.len() * 9 / 10 {
+					let compressed_path = out_dir.join(format!("{name}.{encoding}"));
+					std::fs::write(&compressed_path, compressed).unwrap();
+					write!(
+						encoded,
+						"({encoding:?}, include_bytes!({:?})), ",
+						path_str(&compressed_path)
+					)
+					.unwrap();
+				}
+			}
+		}
+
+		writeln!(
+			generated,
+			"\tStaticFile {{ name: {name:?}, content_type: {content_type:?}, hash: \"{:x}\", contents: include_bytes!({:?}), encoded: &[{encoded}] }},",
+			md5::compute(&contents),
+			path_str(path),
+		)
+		.unwrap();
+	}
+	generated.push(']');
+
+	std::fs::write(out_dir.join("static_files.rs"), generated).unwrap();
+}
+
+fn path_str(path: &Path) -> &str {
+	path.to_str().expect("paths must be UTF-8")
+}
+
+fn is_compressible(mime: &mime_guess::Mime) -> bool {
+	mime.type_() == mime_guess::mime::TEXT
+		|| mime.suffix() == Some(mime_guess::mime::XML)
+		|| mime.suffix() == Some(mime_guess::mime::JSON)
+		|| matches!(mime.subtype().as_str(), "javascript" | "json" | "xml")
+}
+
+fn brotli(data: &[u8]) -> Vec<u8> {
+	let mut ret = Vec::new();
+	{
+		let mut writer = brotli::CompressorWriter::new(&mut ret, 4096, 11, 22);
+		writer.write_all(data).unwrap();
+	}
+	ret
+}
+
+fn zstd(data: &[u8]) -> Vec<u8> {
+	zstd::encode_all(data, 19).unwrap()
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+	let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+	encoder.write_all(data).unwrap();
+	encoder.finish().unwrap()
+}
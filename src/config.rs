@@ -1,24 +1,407 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
+use anyhow::Context as _;
 use figment::providers::{Env, Format, Toml};
 use figment::Figment;
 use serde::Deserialize;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Config {
 	pub address: bindable::BindableAddr,
 	pub index_root: PathBuf,
 	pub thumbnail_tmp: PathBuf,
 	#[serde(default)]
+	pub thumbnail_cache: ThumbnailCache,
+	/// In seconds.
+	#[serde(default = "default_thumbnail_retry_after")]
+	pub thumbnail_retry_after: u64,
+	#[serde(default)]
 	pub log_level: LevelFilter,
+	#[serde(default)]
+	pub symlinks: Symlinks,
 	#[serde(default = "default_exclude_dotfiles")]
 	pub exclude_dotfiles: bool,
+	#[serde(default)]
+	pub name_sort: NameSort,
+	#[serde(default = "default_collation_locale")]
+	pub collation_locale: icu_locid::LanguageIdentifier,
+	#[serde(default = "default_page_size")]
+	pub page_size: usize,
+	#[serde(default = "default_listing_concurrency")]
+	pub listing_concurrency: usize,
+	#[serde(default)]
+	pub directory_sizes: bool,
+	/// In seconds.
+	#[serde(default = "default_directory_sizes_max_age")]
+	pub directory_sizes_max_age: u64,
+	/// Rendered above the listing.
+	#[serde(default)]
+	pub header_names: Vec<String>,
+	/// Rendered below the listing.
+	#[serde(default = "default_readme_names")]
+	pub readme_names: Vec<String>,
+	#[serde(default = "default_text_preview_max_bytes")]
+	pub text_preview_max_bytes: u64,
+	#[serde(default)]
+	pub thumbnailers: Vec<Thumbnailer>,
+	#[serde(default)]
+	pub cache_control: CacheControl,
+	/// Keyed by extension, without the leading dot. Takes precedence over the MIME type guessed from the extension.
+	#[serde(default, deserialize_with = "deserialize_mime_types")]
+	pub mime_types: HashMap<String, mime::Mime>,
+	#[serde(default)]
+	pub active_content: ActiveContent,
+	#[serde(default)]
+	pub security_headers: SecurityHeaders,
+	/// Directories that files can be uploaded to.
+	#[serde(default)]
+	pub writable: Vec<Writable>,
+	/// Where deleted files are moved. Deleting is disabled if there is none.
+	#[serde(default)]
+	pub trash_dir: Option<PathBuf>,
+	/// Where uploads and changes to files are recorded, one JSON object per line.
+	#[serde(default)]
+	pub audit_log: Option<PathBuf>,
+	/// Signs share links. Sharing is disabled if there is none.
+	#[serde(default)]
+	pub share_secret: Option<String>,
+	#[serde(default)]
+	pub limits: Limits,
+	/// Prepended to every route and URL, for serving under a path behind a reverse proxy.
+	/// Starts with a slash and has no trailing slash, or is empty to serve at the root.
+	#[serde(default, deserialize_with = "deserialize_base_path")]
+	pub base_path: String,
+	/// Proxies whose `Forwarded` and `X-Forwarded-For` headers are believed. Peers connected over a Unix socket are always trusted.
+	#[serde(default)]
+	pub trusted_proxies: Vec<IpAddr>,
+	/// The header that trusted proxies put the address of the client in. The other one is ignored, since proxies pass it on from clients as is.
+	#[serde(default)]
+	pub forwarded_header: ForwardedHeader,
+}
+
+impl Config {
+	/// The most specific writable directory containing `dir`, which must be canonical, if any.
+	/// The writable directories are canonicalized as well, so that symlinks cannot lead out of them.
+	pub async fn writable(&self, dir: &Path) -> Option<&Writable> {
+		let mut found = None;
+		for writable in &self.writable {
+			let relative = writable.path.strip_prefix("/").unwrap_or(&writable.path);
+			let Ok(root) = tokio::fs::canonicalize(self.index_root.join(relative)).await else {
+				continue;
+			};
+			let depth = root.components().count();
+			if dir.starts_with(&root) && found.is_none_or(|(found_depth, _)| depth > found_depth) {
+				found = Some((depth, writable));
+			}
+		}
+		found.map(|(_, writable)| writable)
+	}
+
+	/// Deleted entries are renamed into `trash_dir`, which only works within a filesystem, so it must be on the same one as every writable directory that can be managed.
+	pub async fn check_trash_dir(&self) -> anyhow::Result<()> {
+		use std::os::unix::fs::MetadataExt as _;
+
+		let Some(trash_dir) = &self.trash_dir else {
+			return Ok(());
+		};
+		tokio::fs::create_dir_all(trash_dir)
+			.await
+			.context("creating trash_dir")?;
+		let trash_dev = tokio::fs::metadata(trash_dir)
+			.await
+			.context("reading metadata of trash_dir")?
+			.dev();
+		for writable in self.writable.iter().filter(|writable| writable.manage) {
+			let relative = writable.path.strip_prefix("/").unwrap_or(&writable.path);
+			// missing directories are not writable anyway
+			let Ok(metadata) = tokio::fs::metadata(self.index_root.join(relative)).await else {
+				continue;
+			};
+			if metadata.dev() != trash_dev {
+				anyhow::bail!(
+					"trash_dir must be on the same filesystem as the writable directory {:?}, since deleted entries are moved there",
+					writable.path,
+				);
+			}
+		}
+		Ok(())
+	}
+}
+
+fn deserialize_mime_types<'de, D: serde::Deserializer<'de>>(
+	deserializer: D,
+) -> Result<HashMap<String, mime::Mime>, D::Error> {
+	HashMap::<String, String>::deserialize(deserializer)?
+		.into_iter()
+		.map(|(extension, mime)| {
+			let mime = mime.parse().map_err(serde::de::Error::custom)?;
+			Ok((extension.to_ascii_lowercase(), mime))
+		})
+		.collect()
+}
+
+fn deserialize_base_path<'de, D: serde::Deserializer<'de>>(
+	deserializer: D,
+) -> Result<String, D::Error> {
+	let path = String::deserialize(deserializer)?;
+	let path = path.trim_end_matches('/');
+	if !path.is_empty() && !path.starts_with('/') {
+		return Err(serde::de::Error::custom(
+			"`base_path` must start with a slash",
+		));
+	}
+	// so that it can be put in URLs as is
+	if !path
+		.bytes()
+		.all(|byte| byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte))
+	{
+		return Err(serde::de::Error::custom(
+			"`base_path` can only contain letters, digits, slashes, `-`, `.`, `_`, and `~`",
+		));
+	}
+	Ok(path.to_owned())
+}
+
+const fn default_thumbnail_retry_after() -> u64 {
+	24 * 60 * 60
 }
 
 const fn default_exclude_dotfiles() -> bool {
 	true
 }
 
+fn default_collation_locale() -> icu_locid::LanguageIdentifier {
+	icu_locid::LanguageIdentifier::UND
+}
+
+const fn default_page_size() -> usize {
+	1000
+}
+
+const fn default_listing_concurrency() -> usize {
+	32
+}
+
+const fn default_directory_sizes_max_age() -> u64 {
+	10 * 60
+}
+
+fn default_readme_names() -> Vec<String> {
+	["README.md", "README.markdown", "README.txt", "README"]
+		.map(str::to_owned)
+		.into()
+}
+
+const fn default_text_preview_max_bytes() -> u64 {
+	512 * 1024
+}
+
+/// An external program that generates thumbnails, like a freedesktop `.thumbnailer` file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Thumbnailer {
+	/// Without the leading dot. Matched case-insensitively.
+	#[serde(default)]
+	pub extensions: Vec<String>,
+	/// Matched against the MIME types guessed from the extension.
+	#[serde(default)]
+	pub mime_types: Vec<String>,
+	/// The program followed by its arguments, in which `%i` is replaced with the input path, `%o` with the output path, and `%s` with the thumbnail size.
+	/// If there is no `%o`, the thumbnail is read from standard output.
+	pub command: Vec<String>,
+	/// In seconds.
+	#[serde(default = "default_thumbnailer_timeout")]
+	pub timeout: u64,
+}
+
+const fn default_thumbnailer_timeout() -> u64 {
+	30
+}
+
+impl Thumbnailer {
+	pub fn handles(&self, extension: &str) -> bool {
+		self
+			.extensions
+			.iter()
+			.any(|handled| handled.eq_ignore_ascii_case(extension))
+			|| mime_guess::from_ext(extension).iter_raw().any(|mime| {
+				self
+					.mime_types
+					.iter()
+					.any(|handled| handled.eq_ignore_ascii_case(mime))
+			})
+	}
+}
+
+/// The `Cache-Control` header sent with each kind of response. Empty policies are not sent.
+#[derive(Deserialize, Debug, Clone)]
+pub struct CacheControl {
+	#[serde(default = "default_cache_control")]
+	pub listings: String,
+	#[serde(default = "default_cache_control")]
+	pub files: String,
+	#[serde(default = "default_cache_control")]
+	pub thumbnails: String,
+	#[serde(default = "default_cache_control")]
+	pub static_files: String,
+}
+
+impl Default for CacheControl {
+	fn default() -> Self {
+		Self {
+			listings: default_cache_control(),
+			files: default_cache_control(),
+			thumbnails: default_cache_control(),
+			static_files: default_cache_control(),
+		}
+	}
+}
+
+/// Responses can be cached, but must be revalidated before each use.
+fn default_cache_control() -> String {
+	"no-cache".to_owned()
+}
+
+/// Limits on requests and bandwidth, so that single clients cannot use up the server. Zero means no limit.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Limits {
+	/// Requests per client per minute, across all routes.
+	#[serde(default)]
+	pub requests_per_minute: u32,
+	/// Thumbnails generated per client per minute. Thumbnails that are already cached do not count.
+	#[serde(default)]
+	pub thumbnails_per_minute: u32,
+	/// Bytes per second for each file being sent.
+	#[serde(default)]
+	pub download_bytes_per_second: u64,
+	/// Bytes per second for all files being sent together.
+	#[serde(default)]
+	pub total_download_bytes_per_second: u64,
+	/// A header naming the user, set by an authenticating proxy, that identifies clients instead of their IP address.
+	/// Only believed in requests from `trusted_proxies`.
+	#[serde(default)]
+	pub user_header: Option<String>,
+}
+
+/// A directory that files can be uploaded to, along with its subdirectories.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Writable {
+	/// Relative to `index_root`, like the paths in URLs.
+	pub path: PathBuf,
+	/// The maximum total size of the files in one upload.
+	#[serde(default = "default_max_upload_bytes")]
+	pub max_upload_bytes: u64,
+	/// Whether folders can also be created, and entries renamed, moved, and deleted.
+	#[serde(default)]
+	pub manage: bool,
+}
+
+const fn default_max_upload_bytes() -> u64 {
+	100 * 1024 * 1024
+}
+
+/// Which symlinks are followed when listing, serving, and thumbnailing files.
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Symlinks {
+	/// Wherever they point.
+	Follow,
+	/// Only if they point somewhere within `index_root`.
+	#[default]
+	WithinRoot,
+	/// Never, and they are not listed.
+	Hide,
+}
+
+impl Symlinks {
+	/// Whether a path that resolves to `canonical` may be accessed, where `through_link` is whether resolving it followed any symlinks.
+	/// `root` must be canonical.
+	pub fn allows(self, root: &Path, canonical: &Path, through_link: bool) -> bool {
+		match self {
+			Self::Follow => true,
+			Self::WithinRoot => canonical.starts_with(root),
+			Self::Hide => !through_link,
+		}
+	}
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardedHeader {
+	/// `Forwarded`, with the public host in `host`.
+	Forwarded,
+	/// `X-Forwarded-For`, with the public host in `X-Forwarded-Host`.
+	#[default]
+	XForwardedFor,
+}
+
+/// How files that could run scripts in the browser, namely HTML and SVG, are served.
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ActiveContent {
+	/// Displayed inline, with a `Content-Security-Policy` that sandboxes them into a unique origin without scripts.
+	#[default]
+	Sandbox,
+	/// Always downloaded rather than displayed.
+	Attachment,
+}
+
+/// Headers sent with every response unless it already has them. Empty values are not sent.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SecurityHeaders {
+	/// Only sent with HTML responses.
+	#[serde(default = "default_content_security_policy")]
+	pub content_security_policy: String,
+	#[serde(default = "default_referrer_policy")]
+	pub referrer_policy: String,
+	#[serde(default = "default_frame_options")]
+	pub frame_options: String,
+}
+
+impl Default for SecurityHeaders {
+	fn default() -> Self {
+		Self {
+			content_security_policy: default_content_security_policy(),
+			referrer_policy: default_referrer_policy(),
+			frame_options: default_frame_options(),
+		}
+	}
+}
+
+/// Everything comes from dexr itself, and previews of documents can be framed.
+fn default_content_security_policy() -> String {
+	"default-src 'self'; img-src 'self' data:; object-src 'none'; base-uri 'none'; form-action 'self'; frame-ancestors 'self'".to_owned()
+}
+
+fn default_referrer_policy() -> String {
+	"same-origin".to_owned()
+}
+
+fn default_frame_options() -> String {
+	"SAMEORIGIN".to_owned()
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailCache {
+	/// Named after the path relative to `index_root`, directly in `thumbnail_tmp`.
+	#[default]
+	Private,
+	/// Following the freedesktop thumbnail managing standard, with `thumbnail_tmp` as the thumbnail directory.
+	Freedesktop,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum NameSort {
+	/// Case-insensitive, locale-aware, with runs of digits compared by their numeric value.
+	#[default]
+	Natural,
+	/// Byte-wise comparison of the names.
+	Bytes,
+}
+
 #[derive(Deserialize, Debug, Default, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
 pub enum LevelFilter {
@@ -1,6 +1,7 @@
 use std::fmt::Display;
 use std::io;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::response::{IntoResponse, Response};
 use http::status::StatusCode as HttpStatus;
@@ -34,14 +35,31 @@ pub struct Io<E> {
 	pub error: E,
 }
 
+/// The context is only logged, since it can include details of the filesystem.
 impl<E: Flavor> IntoResponse for Io<E> {
 	fn into_response(self) -> Response {
-		let status = match self.error.deref().kind() {
-			io::ErrorKind::NotFound => HttpStatus::NOT_FOUND,
-			_ => HttpStatus::INTERNAL_SERVER_ERROR,
+		let (status, message) = match self.error.deref().kind() {
+			io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => {
+				(HttpStatus::NOT_FOUND, "not found")
+			}
+			io::ErrorKind::PermissionDenied => (HttpStatus::FORBIDDEN, "permission denied"),
+			io::ErrorKind::InvalidInput | io::ErrorKind::InvalidFilename => {
+				(HttpStatus::BAD_REQUEST, "invalid file name")
+			}
+			io::ErrorKind::Unsupported => {
+				tracing::error!("{self}");
+				(
+					HttpStatus::NOT_IMPLEMENTED,
+					"not supported by the filesystem",
+				)
+			}
+			_ => {
+				tracing::error!("{self}");
+				(HttpStatus::INTERNAL_SERVER_ERROR, "internal error")
+			}
 		};
 
-		(status, self.to_string()).into_response()
+		response(status, message)
 	}
 }
 
@@ -54,6 +72,44 @@ pub struct BadRequest<S>(pub S);
 
 impl<S: ToString> IntoResponse for BadRequest<S> {
 	fn into_response(self) -> Response {
-		(HttpStatus::BAD_REQUEST, self.0.to_string()).into_response()
+		response(HttpStatus::BAD_REQUEST, self.0.to_string())
 	}
 }
+
+#[derive(Debug, thiserror::Error)]
+#[error("not found")]
+pub struct NotFound;
+
+impl IntoResponse for NotFound {
+	fn into_response(self) -> Response {
+		response(HttpStatus::NOT_FOUND, "not found")
+	}
+}
+
+/// Along with how long the client should wait, rounded up to whole seconds for `Retry-After`.
+#[derive(Debug, thiserror::Error)]
+#[error("too many requests")]
+pub struct TooManyRequests(pub Duration);
+
+impl IntoResponse for TooManyRequests {
+	fn into_response(self) -> Response {
+		let seconds = self.0.as_secs() + u64::from(self.0.subsec_nanos() > 0);
+		let mut response = response(HttpStatus::TOO_MANY_REQUESTS, "too many requests");
+		response
+			.headers_mut()
+			.insert(http::header::RETRY_AFTER, http::HeaderValue::from(seconds));
+		response
+	}
+}
+
+/// Attached to error responses so that the error page can show the message in the format that the client asked for.
+#[derive(Debug, Clone)]
+pub struct Message(pub String);
+
+/// A plain-text error response, which is rendered as HTML or JSON by `routes::errors` if the client prefers.
+pub fn response(status: HttpStatus, message: impl Into<String>) -> Response {
+	let message = message.into();
+	let mut response = (status, message.clone()).into_response();
+	response.extensions_mut().insert(Message(message));
+	response
+}
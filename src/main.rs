@@ -18,15 +18,17 @@
 #![deny(unsafe_code)]
 #![allow(clippy::let_underscore_drop, clippy::unused_async)]
 
-use anyhow::{Context as _, Result};
+use anyhow::{bail, Context as _, Result};
 use tracing_subscriber::filter::FilterFn;
 use tracing_subscriber::layer::SubscriberExt as _;
 use tracing_subscriber::util::SubscriberInitExt as _;
 
 mod config;
 mod error;
+mod markup;
 mod routes;
 mod server;
+mod share;
 mod thumbnail;
 mod util;
 
@@ -37,9 +39,22 @@ fn main() -> Result<()> {
 }
 
 async fn main_() -> Result<()> {
-	let config = config::load().context("loading config")?;
+	let mut config = config::load().context("loading config")?;
+	let mut args = std::env::args().skip(1);
+	if let Some(command) = args.next() {
+		return match command.as_str() {
+			"share" => share::command(&config, args),
+			_ => bail!("unknown command {command:?}"),
+		};
+	}
 	init_logging(config.log_level.into());
-	let app = routes::configure();
+	// so that symlinks can be checked against it
+	config.index_root = tokio::fs::canonicalize(&config.index_root)
+		.await
+		.context("canonicalizing index_root")?;
+	config.check_trash_dir().await?;
+	thumbnail::discover_renderers();
+	let app = routes::configure(&config);
 	server::serve(app, config).await.context("running server")
 }
 
@@ -0,0 +1,77 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+use once_cell::sync::Lazy;
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+use crate::util::join_paths;
+
+/// Matches `static/highlight.css`.
+const HIGHLIGHT_CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+
+pub fn lowercase_extension(path: &Path) -> Option<String> {
+	path
+		.extension()
+		.and_then(std::ffi::OsStr::to_str)
+		.map(str::to_ascii_lowercase)
+}
+
+/// Render Markdown as sanitized HTML.
+///
+/// Relative links are resolved against `base_url`.
+pub fn markdown(contents: &str, base_url: &str) -> String {
+	let parser = pulldown_cmark::Parser::new_ext(contents, pulldown_cmark::Options::all());
+	let mut html = String::new();
+	pulldown_cmark::html::push_html(&mut html, parser);
+	sanitize(&html, base_url)
+}
+
+/// Relative links are resolved against `base_url`.
+pub fn sanitize(html: &str, base_url: &str) -> String {
+	ammonia::Builder::default()
+		.url_relative(ammonia::UrlRelative::Custom(Box::new(resolve_relative(
+			base_url.to_owned(),
+		))))
+		.clean(html)
+		.to_string()
+}
+
+fn resolve_relative(
+	base_url: String,
+) -> impl for<'url> Fn(&'url str) -> Option<Cow<'url, str>> + Send + Sync {
+	move |url| {
+		if url.starts_with(['/', '#', '?']) {
+			Some(Cow::Borrowed(url))
+		} else {
+			Some(Cow::Owned(join_paths([base_url.as_str(), url])))
+		}
+	}
+}
+
+pub fn plain_text(contents: &str) -> String {
+	format!("<pre>{}</pre>", html_escape::encode_text(contents))
+}
+
+/// Syntax-highlight source code, using the syntax associated with `extension` if there is one.
+///
+/// The highlighting uses classes that are styled by `static/highlight.css`.
+pub fn highlight(contents: &str, extension: Option<&str>) -> String {
+	let syntax = extension
+		.and_then(|extension| SYNTAXES.find_syntax_by_extension(extension))
+		.unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+
+	let mut generator =
+		ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, HIGHLIGHT_CLASS_STYLE);
+	for line in LinesWithEndings::from(contents) {
+		if let Err(error) = generator.parse_html_for_line_which_includes_newline(line) {
+			tracing::debug!("highlighting failed, falling back to plain text: {error}");
+			return plain_text(contents);
+		}
+	}
+
+	format!("<pre class=\"hl-code\">{}</pre>", generator.finalize())
+}
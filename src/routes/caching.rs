@@ -0,0 +1,100 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::response::{IntoResponse as _, Response};
+use http::header::{CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use http::{HeaderMap, HeaderValue, StatusCode};
+
+/// Lets clients revalidate their cached copy of a response.
+#[derive(Debug)]
+pub(super) struct Validators {
+	etag: HeaderValue,
+	last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+	/// The entity tag is derived from `key`, which must change whenever the response changes.
+	/// A weak tag only promises that the responses are equivalent rather than byte-for-byte identical.
+	pub(super) fn new(key: &str, weak: bool, last_modified: Option<SystemTime>) -> Self {
+		Self::from_digest(md5::compute(key), weak, last_modified)
+	}
+
+	/// Like `new`, for keys that are hashed as they are produced rather than collected first.
+	pub(super) fn from_digest(
+		hash: md5::Digest,
+		weak: bool,
+		last_modified: Option<SystemTime>,
+	) -> Self {
+		let etag = if weak {
+			format!("W/\"{hash:x}\"")
+		} else {
+			format!("\"{hash:x}\"")
+		};
+		Self {
+			etag: HeaderValue::from_str(&etag).unwrap(),
+			last_modified,
+		}
+	}
+
+	/// Whether the client's cached copy is still valid, according to `If-None-Match` or, if there is none, `If-Modified-Since`.
+	pub(super) fn is_fresh(&self, headers: &HeaderMap) -> bool {
+		if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+			let ours = strip_weak(self.etag.to_str().unwrap());
+			return if_none_match.to_str().is_ok_and(|if_none_match| {
+				if_none_match
+					.split(',')
+					.map(str::trim)
+					.any(|theirs| theirs == "*" || strip_weak(theirs) == ours)
+			});
+		}
+
+		let Some(last_modified) = self.last_modified else {
+			return false;
+		};
+		headers
+			.get(IF_MODIFIED_SINCE)
+			.and_then(|since| since.to_str().ok())
+			.and_then(|since| httpdate::parse_http_date(since).ok())
+			.is_some_and(|since| whole_seconds(last_modified) <= whole_seconds(since))
+	}
+
+	pub(super) fn not_modified(&self) -> Response {
+		let mut response = StatusCode::NOT_MODIFIED.into_response();
+		self.apply(&mut response);
+		response
+	}
+
+	pub(super) fn apply(&self, response: &mut Response) {
+		let headers = response.headers_mut();
+		headers.insert(ETAG, self.etag.clone());
+		if let Some(last_modified) = self.last_modified {
+			headers.insert(
+				LAST_MODIFIED,
+				HeaderValue::from_str(&httpdate::fmt_http_date(last_modified)).unwrap(),
+			);
+		}
+	}
+}
+
+/// Weak comparison, as required for `If-None-Match`.
+fn strip_weak(etag: &str) -> &str {
+	etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+/// HTTP dates only have a resolution of one second.
+fn whole_seconds(time: SystemTime) -> u64 {
+	time
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |duration| duration.as_secs())
+}
+
+/// Set the configured `Cache-Control` header on successful and not-modified responses.
+/// An empty policy sends no header.
+pub(super) fn set_cache_control(response: &mut Response, policy: &str) {
+	let status = response.status();
+	if policy.is_empty() || !(status.is_success() || status == StatusCode::NOT_MODIFIED) {
+		return;
+	}
+	if let Ok(policy) = HeaderValue::from_str(policy) {
+		response.headers_mut().insert(CACHE_CONTROL, policy);
+	}
+}
@@ -0,0 +1,148 @@
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use axum::body::{boxed, Full};
+use axum::middleware::Next;
+use axum::response::Response;
+use http::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderMap, HeaderValue, Request, StatusCode};
+
+use crate::config::Config;
+use crate::error::Message;
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+	Html,
+	Json,
+}
+
+impl Format {
+	/// The first of the supported formats in `Accept`, if any. Other clients, like `<img>` elements, get plain text.
+	fn preferred(headers: &HeaderMap) -> Option<Self> {
+		headers
+			.get_all(ACCEPT)
+			.iter()
+			.filter_map(|value| value.to_str().ok())
+			.flat_map(|value| value.split(','))
+			.find_map(
+				|item| match item.split(';').next().unwrap_or_default().trim() {
+					"text/html" | "application/xhtml+xml" => Some(Self::Html),
+					"application/json" => Some(Self::Json),
+					_ => None,
+				},
+			)
+	}
+}
+
+/// Render error responses as HTML pages or JSON, depending on what the client asked for.
+pub(super) async fn render<B>(request: Request<B>, next: Next<B>) -> Response {
+	let format = Format::preferred(request.headers());
+	let path = request.uri().path().to_owned();
+	let config = Arc::clone(request.extensions().get::<Arc<Config>>().unwrap());
+	let response = next.run(request).await;
+
+	let status = response.status();
+	let Some(format) = format else {
+		return response;
+	};
+	if !(status.is_client_error() || status.is_server_error()) {
+		return response;
+	}
+	// some errors, like the password prompt of shares, are already pages
+	let is_page = response
+		.headers()
+		.get(CONTENT_TYPE)
+		.and_then(|content_type| content_type.to_str().ok())
+		.is_some_and(|content_type| content_type.starts_with("text/html"));
+	if is_page {
+		return response;
+	}
+
+	let message = response.extensions().get::<Message>().map_or_else(
+		|| {
+			status
+				.canonical_reason()
+				.unwrap_or("error")
+				.to_ascii_lowercase()
+		},
+		|message| message.0.clone(),
+	);
+	let (content_type, body) = match format {
+		Format::Html => (
+			"text/html; charset=utf-8",
+			page(status, &message, &config.base_path, &path),
+		),
+		Format::Json => (
+			"application/json",
+			serde_json::json!({ "status": status.as_u16(), "error": message }).to_string(),
+		),
+	};
+
+	let (mut parts, _) = response.into_parts();
+	parts.headers.remove(CONTENT_LENGTH);
+	parts
+		.headers
+		.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+	Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+/// `path` is within `base_path`.
+fn page(status: StatusCode, message: &str, base_path: &str, path: &str) -> String {
+	let title = format!(
+		"{} {}",
+		status.as_str(),
+		status.canonical_reason().unwrap_or("Error")
+	);
+	let mut ret = String::new();
+	let up = format!("{base_path}{}", parent_url(path));
+	super::fs::write_document_start(&mut ret, base_path, &title, Some(&up));
+	write!(
+		ret,
+		"<a href=\"{}\" rel=\"up\">Go up</a>\
+		<h1>{}</h1>\
+		<p>{}</p>\
+		</body></html>",
+		html_escape::encode_double_quoted_attribute(&up),
+		html_escape::encode_text(&title),
+		html_escape::encode_text(message),
+	)
+	.unwrap();
+	ret
+}
+
+/// The listing containing `path`, or the root listing if `path` is not within `/fs`.
+/// Listings within shares are under `/s/<token>/fs`.
+fn parent_url(path: &str) -> &str {
+	let Some(root) = listing_root(path) else {
+		return "/fs/";
+	};
+	match path.trim_end_matches('/').rsplit_once('/') {
+		Some((parent, _)) if parent.len() >= root.len() => parent,
+		_ => root,
+	}
+}
+
+/// `/fs/`, or `/s/<token>/fs/` within shares.
+fn listing_root(path: &str) -> Option<&str> {
+	if path.starts_with("/fs/") {
+		return Some("/fs/");
+	}
+	let token_len = path.strip_prefix("/s/")?.find('/')?;
+	let len = "/s/".len() + token_len + "/fs/".len();
+	path[("/s/".len() + token_len)..]
+		.starts_with("/fs/")
+		.then(|| &path[..len])
+}
+
+#[test]
+fn test_parent_url() {
+	assert_eq!(parent_url("/fs/photos/2020/img.jpg"), "/fs/photos/2020");
+	assert_eq!(parent_url("/fs/photos/"), "/fs/");
+	assert_eq!(parent_url("/fs/"), "/fs/");
+	assert_eq!(parent_url("/thumb/photos/img.jpg"), "/fs/");
+	assert_eq!(
+		parent_url("/s/abc.def/fs/photos/img.jpg"),
+		"/s/abc.def/fs/photos"
+	);
+	assert_eq!(parent_url("/s/abc.def/fs/photos"), "/s/abc.def/fs/");
+}
@@ -0,0 +1,183 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::extract::ConnectInfo;
+use axum::middleware::Next;
+use axum::response::Response;
+use http::header::{HeaderName, FORWARDED};
+use http::{HeaderMap, Request};
+use tracing::Instrument as _;
+
+use crate::config::{Config, ForwardedHeader};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// The address of the client, as far as it can be known.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ClientAddr(pub IpAddr);
+
+/// Added if the request came from a trusted proxy.
+#[derive(Debug, Clone)]
+pub(super) struct Proxied {
+	/// The host that the client asked for, as forwarded by the proxy.
+	pub host: Option<String>,
+}
+
+/// Find the address of the client, following the configured forwarded header through trusted proxies, and log everything about the request along with it.
+pub(super) async fn client_addr<B>(mut request: Request<B>, next: Next<B>) -> Response {
+	let config = Arc::clone(request.extensions().get::<Arc<Config>>().unwrap());
+	// there is none for Unix sockets, where the peer can only be a local proxy
+	let peer = request
+		.extensions()
+		.get::<ConnectInfo<SocketAddr>>()
+		.map(|ConnectInfo(address)| address.ip());
+	let is_trusted = |addr| config.trusted_proxies.contains(&addr);
+	let headers = request.headers();
+	let addr = client(
+		peer,
+		&forwarded_for(headers, config.forwarded_header),
+		is_trusted,
+	);
+	let proxied = peer.is_none_or(is_trusted).then(|| Proxied {
+		host: forwarded_host(headers, config.forwarded_header),
+	});
+
+	if let Some(proxied) = proxied {
+		request.extensions_mut().insert(proxied);
+	}
+
+	let span = tracing::info_span!("request", client = tracing::field::Empty);
+	if let Some(addr) = addr {
+		span.record("client", tracing::field::display(addr));
+		request.extensions_mut().insert(ClientAddr(addr));
+	}
+	next.run(request).instrument(span).await
+}
+
+/// Walks the chain of proxies back from the peer for as long as they are trusted, since untrusted ones could have made up the rest.
+/// A hop that is unknown or obfuscated ends the chain at the proxy that reported it.
+fn client(
+	peer: Option<IpAddr>,
+	hops: &[Option<IpAddr>],
+	is_trusted: impl Fn(IpAddr) -> bool,
+) -> Option<IpAddr> {
+	if peer.is_some_and(|peer| !is_trusted(peer)) {
+		return peer;
+	}
+	let mut addr = peer;
+	for &hop in hops.iter().rev() {
+		let Some(hop) = hop else {
+			break;
+		};
+		addr = Some(hop);
+		if !is_trusted(hop) {
+			break;
+		}
+	}
+	addr
+}
+
+/// The comma-separated values of all of the headers called `name`, in order.
+fn values(headers: &HeaderMap, name: HeaderName) -> impl Iterator<Item = &str> {
+	headers
+		.get_all(name)
+		.into_iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+}
+
+/// The value of `name` in an element of `Forwarded`, like `for=192.0.2.60;proto=http`.
+fn forwarded_param<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+	element
+		.split(';')
+		.filter_map(|pair| pair.split_once('='))
+		.find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+		.map(|(_, value)| value.trim().trim_matches('"'))
+}
+
+/// The `for` addresses of each hop, from the client to the last proxy.
+fn forwarded_for(headers: &HeaderMap, header: ForwardedHeader) -> Vec<Option<IpAddr>> {
+	match header {
+		ForwardedHeader::Forwarded => values(headers, FORWARDED)
+			.map(|element| forwarded_param(element, "for").and_then(parse_node))
+			.collect(),
+		ForwardedHeader::XForwardedFor => values(headers, X_FORWARDED_FOR).map(parse_node).collect(),
+	}
+}
+
+/// The host as added by the last proxy.
+fn forwarded_host(headers: &HeaderMap, header: ForwardedHeader) -> Option<String> {
+	let host = match header {
+		ForwardedHeader::Forwarded => forwarded_param(values(headers, FORWARDED).last()?, "host")?,
+		ForwardedHeader::XForwardedFor => values(headers, X_FORWARDED_HOST).last()?.trim(),
+	};
+	(!host.is_empty()).then(|| host.to_owned())
+}
+
+/// An address, optionally quoted, with an optional port, and with brackets around IPv6 addresses if there is a port.
+fn parse_node(node: &str) -> Option<IpAddr> {
+	let node = node.trim().trim_matches('"');
+	if let Some(rest) = node.strip_prefix('[') {
+		return rest.split(']').next()?.parse().ok();
+	}
+	node.parse().ok().or_else(|| {
+		let (ip, _port) = node.rsplit_once(':')?;
+		ip.parse().ok()
+	})
+}
+
+#[test]
+fn test_client() {
+	use ForwardedHeader::{Forwarded, XForwardedFor};
+
+	let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+	let proxy = ip("10.0.0.1");
+	let is_trusted = |addr| addr == proxy;
+
+	let mut headers = HeaderMap::new();
+	headers.insert(
+		X_FORWARDED_FOR,
+		"203.0.113.1, 198.51.100.2:1234, 10.0.0.1".parse().unwrap(),
+	);
+	let hops = forwarded_for(&headers, XForwardedFor);
+	// the first address could have been made up by the client
+	assert_eq!(
+		client(Some(proxy), &hops, is_trusted),
+		Some(ip("198.51.100.2"))
+	);
+	assert_eq!(
+		client(Some(ip("198.51.100.3")), &hops, is_trusted),
+		Some(ip("198.51.100.3"))
+	);
+
+	headers.insert(
+		FORWARDED,
+		r#"for=192.0.2.60;proto=http, For="[2001:db8:cafe::17]:4711";host=example.com"#
+			.parse()
+			.unwrap(),
+	);
+	// sent by the client, and passed on by a proxy that only sets `X-Forwarded-For`
+	assert_eq!(forwarded_for(&headers, XForwardedFor), hops);
+	assert_eq!(forwarded_host(&headers, XForwardedFor), None);
+
+	let hops = forwarded_for(&headers, Forwarded);
+	assert_eq!(
+		hops,
+		[Some(ip("192.0.2.60")), Some(ip("2001:db8:cafe::17"))]
+	);
+	assert_eq!(
+		forwarded_host(&headers, Forwarded).as_deref(),
+		Some("example.com")
+	);
+	// Unix sockets
+	assert_eq!(
+		client(None, &hops, is_trusted),
+		Some(ip("2001:db8:cafe::17"))
+	);
+
+	headers.insert(FORWARDED, "for=unknown".parse().unwrap());
+	let hops = forwarded_for(&headers, Forwarded);
+	assert_eq!(client(None, &hops, is_trusted), None);
+	assert_eq!(client(Some(proxy), &hops, is_trusted), Some(proxy));
+}
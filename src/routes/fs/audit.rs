@@ -0,0 +1,96 @@
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::extract::{FromRequest, RequestParts};
+use http::{Extensions, HeaderMap};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt as _;
+
+use crate::config::Config;
+use crate::routes::forwarded::ClientAddr;
+use crate::routes::limits;
+
+/// Who made a change, as far as it can be known.
+#[derive(Debug, Default, Serialize)]
+pub(super) struct Client {
+	/// As found by following trusted proxies.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	client: Option<IpAddr>,
+	/// As named by `user_header`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	user: Option<String>,
+}
+
+impl Client {
+	pub(super) fn new(extensions: &Extensions, headers: &HeaderMap) -> Self {
+		let config = extensions.get::<Arc<Config>>().unwrap();
+		Self {
+			client: extensions.get::<ClientAddr>().map(|&ClientAddr(addr)| addr),
+			user: limits::user(extensions, headers, config).map(str::to_owned),
+		}
+	}
+}
+
+#[axum::async_trait]
+impl<B: Send> FromRequest<B> for Client {
+	type Rejection = Infallible;
+
+	async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+		Ok(Self::new(request.extensions(), request.headers()))
+	}
+}
+
+/// A change to the files in the index, as recorded in the audit log.
+#[derive(Debug, Serialize)]
+pub(super) struct Record<'a> {
+	pub action: &'static str,
+	/// As in URLs.
+	pub path: &'a Path,
+	/// Where the entry was moved to, as in URLs, or where it was moved to in the trash.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub destination: Option<&'a Path>,
+	#[serde(flatten)]
+	pub client: &'a Client,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+	time: String,
+	#[serde(flatten)]
+	record: &'a Record<'a>,
+}
+
+/// Failures are only logged, since the change has already been made.
+pub(super) async fn record(config: &Config, record: Record<'_>) {
+	tracing::info!(?record, "files changed");
+
+	let Some(audit_log) = &config.audit_log else {
+		return;
+	};
+	let time = time::OffsetDateTime::now_utc()
+		.format(&time::format_description::well_known::Rfc3339)
+		.unwrap();
+	let mut line = serde_json::to_string(&Line {
+		time,
+		record: &record,
+	})
+	.unwrap();
+	line.push('\n');
+
+	let result = async {
+		let mut file = tokio::fs::OpenOptions::new()
+			.append(true)
+			.create(true)
+			.open(audit_log)
+			.await?;
+		// a single write, so that concurrent records are not interleaved
+		file.write_all(line.as_bytes()).await?;
+		file.flush().await
+	}
+	.await;
+	if let Err(error) = result {
+		tracing::error!(?audit_log, "could not write to audit log: {error}");
+	}
+}
@@ -0,0 +1,75 @@
+use std::cmp::Ordering;
+
+use icu_collator::{Collator, CollatorError, CollatorOptions, Numeric, Strength};
+
+use crate::config::{Config, NameSort};
+
+pub(super) enum Names {
+	Natural(Box<Collator>),
+	Bytes,
+}
+
+impl Names {
+	pub(super) fn new(config: &Config) -> Self {
+		match config.name_sort {
+			NameSort::Natural => match natural_collator(&config.collation_locale) {
+				Ok(collator) => Self::Natural(Box::new(collator)),
+				Err(error) => {
+					tracing::warn!(locale = %config.collation_locale, "could not create collator, falling back to byte-wise name sorting: {error}");
+					Self::Bytes
+				}
+			},
+			NameSort::Bytes => Self::Bytes,
+		}
+	}
+
+	pub(super) fn compare(&self, a: &str, b: &str) -> Ordering {
+		match self {
+			// names that the collator considers equal, like `a` and `A`, are still ordered deterministically
+			Self::Natural(collator) => collator.compare(a, b).then_with(|| a.cmp(b)),
+			Self::Bytes => a.cmp(b),
+		}
+	}
+}
+
+fn natural_collator(locale: &icu_locid::LanguageIdentifier) -> Result<Collator, CollatorError> {
+	let mut options = CollatorOptions::new();
+	// secondary strength ignores case but not accents
+	options.strength = Some(Strength::Secondary);
+	options.numeric = Some(Numeric::On);
+	Collator::try_new(&locale.into(), options)
+}
+
+#[test]
+fn test_natural_names() {
+	let names = Names::Natural(Box::new(
+		natural_collator(&icu_locid::LanguageIdentifier::UND).unwrap(),
+	));
+
+	let mut sorted = [
+		"img10.jpg",
+		"Img3.jpg",
+		"img2.jpg",
+		"b.txt",
+		"A.txt",
+		"élan",
+		"elan",
+	];
+	sorted.sort_by(|a, b| names.compare(a, b));
+	assert_eq!(
+		sorted,
+		[
+			"A.txt",
+			"b.txt",
+			"elan",
+			"élan",
+			"img2.jpg",
+			"Img3.jpg",
+			"img10.jpg"
+		]
+	);
+
+	let mut sorted = ["img10.jpg", "img2.jpg", "B", "a"];
+	sorted.sort_by(|a, b| Names::Bytes.compare(a, b));
+	assert_eq!(sorted, ["B", "a", "img10.jpg", "img2.jpg"]);
+}
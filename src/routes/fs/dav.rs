@@ -0,0 +1,663 @@
+use std::fmt::Write as _;
+use std::os::linux::fs::MetadataExt as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use axum::extract;
+use axum::handler::Handler as _;
+use axum::response::{ErrorResponse, IntoResponse as _, Redirect, Response};
+use axum::routing::{any, Router};
+use futures::StreamExt as _;
+use http::header::{HeaderName, ALLOW, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderMap, Request, StatusCode};
+use hyper::Body;
+use percent_encoding::percent_decode_str;
+
+use super::audit::Client;
+use super::dir_sizes::DirSizes;
+use super::manage::{self, Action, Transfer};
+use super::{file, read_raw_entries, upload, Entry, Loader, Size};
+use crate::config::Config;
+use crate::error::{self, io_ctx};
+use crate::routes::caching::set_cache_control;
+use crate::util::encode_path;
+
+const DAV: HeaderName = HeaderName::from_static("dav");
+const DEPTH: HeaderName = HeaderName::from_static("depth");
+const DESTINATION: HeaderName = HeaderName::from_static("destination");
+const OVERWRITE: HeaderName = HeaderName::from_static("overwrite");
+
+const METHODS: &str = "OPTIONS, GET, HEAD, PROPFIND, PROPPATCH, PUT, MKCOL, COPY, MOVE, DELETE";
+
+/// Property updates are small, so anything longer is not read.
+const MAX_PROPPATCH_BYTES: usize = 64 * 1024;
+
+/// The same tree as `/fs` over DAV, class 1 (without locking).
+/// Changes are only allowed where they would be allowed through `/fs`.
+pub async fn handler(
+	user_path: Option<extract::Path<PathBuf>>,
+	extract::Extension(config): extract::Extension<Arc<Config>>,
+	extract::Extension(dir_sizes): extract::Extension<Arc<DirSizes>>,
+	request: Request<Body>,
+) -> Result<Response, ErrorResponse> {
+	// the root of the share is routed without a path
+	let user_path = user_path.map_or_else(|| PathBuf::from("/"), |extract::Path(path)| path);
+	crate::routes::assert_path_safe(&user_path)?;
+	if config.exclude_dotfiles && crate::routes::is_hidden_path(&user_path) {
+		return Err(error::NotFound.into());
+	}
+
+	let client = Client::new(request.extensions(), request.headers());
+	Ok(match request.method().as_str() {
+		"OPTIONS" => ([(DAV, "1"), (ALLOW, METHODS)], StatusCode::OK).into_response(),
+		"GET" | "HEAD" => get(request, &config, &user_path).await?,
+		"PROPFIND" => propfind(request.headers(), &config, &dir_sizes, &user_path).await?,
+		"PROPPATCH" => proppatch(request, &config, &user_path).await?,
+		// the root has no path to extract, and is a folder anyway
+		"PUT" if user_path.parent().is_none() => {
+			return Err(error::response(StatusCode::METHOD_NOT_ALLOWED, "the root is a folder").into());
+		}
+		"PUT" => upload::put.call(request).await,
+		"MKCOL" => mkcol(request.headers(), &config, &client, &user_path).await?,
+		"COPY" => transfer(true, request.headers(), &config, &client, &user_path).await?,
+		"MOVE" => transfer(false, request.headers(), &config, &client, &user_path).await?,
+		"DELETE" => {
+			manage::apply(&config, &client, &user_path, Action::Delete).await?;
+			StatusCode::NO_CONTENT.into_response()
+		}
+		_ => ([(ALLOW, METHODS)], StatusCode::METHOD_NOT_ALLOWED).into_response(),
+	})
+}
+
+/// Files are served like in `/fs`, and directories redirect to their listing.
+async fn get(
+	request: Request<Body>,
+	config: &Config,
+	user_path: &Path,
+) -> Result<Response, ErrorResponse> {
+	let relative_path = user_path.strip_prefix("/").unwrap();
+	let fs_path = crate::routes::resolve_path(config, relative_path).await?;
+	let metadata = tokio::fs::metadata(&fs_path)
+		.await
+		.map_err(io_ctx("reading metadata"))?;
+
+	if metadata.is_dir() {
+		return Ok(
+			Redirect::to(&format!(
+				"{}/fs{}",
+				config.base_path,
+				encode_path(user_path)
+			))
+			.into_response(),
+		);
+	}
+	let mut response = file::send(request, config, fs_path, false).await;
+	set_cache_control(&mut response, &config.cache_control.files);
+	Ok(response)
+}
+
+/// A resource as described in `PROPFIND` responses.
+struct Resource {
+	href: String,
+	name: String,
+	/// `None` for directories.
+	file: Option<FileProps>,
+	/// Seconds since the Unix epoch.
+	mtime: i64,
+}
+
+struct FileProps {
+	len: u64,
+	content_type: mime::Mime,
+}
+
+impl Resource {
+	fn from_entry(config: &Config, dir_path: &Path, entry: &Entry) -> Self {
+		let file = match entry.size {
+			Size::Bytes(len) => Some(FileProps {
+				len,
+				content_type: file::content_type(config, Path::new(&entry.name)),
+			}),
+			Size::Items(_) => None,
+		};
+		Self {
+			href: encode_path(&dir_path.join(&entry.name)) + if file.is_none() { "/" } else { "" },
+			name: entry.name.clone(),
+			file,
+			mtime: entry.mtime,
+		}
+	}
+
+	/// `root` is where the tree is served.
+	fn write(&self, out: &mut String, root: &str) {
+		write!(
+			out,
+			"<D:response>\
+				<D:href>{root}{}</D:href>\
+				<D:propstat>\
+					<D:prop>\
+						<D:displayname>{}</D:displayname>\
+						<D:getlastmodified>{}</D:getlastmodified>",
+			html_escape::encode_text(&self.href),
+			html_escape::encode_text(&self.name),
+			httpdate::fmt_http_date(
+				SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(self.mtime).unwrap_or(0))
+			),
+			root = html_escape::encode_text(root),
+		)
+		.unwrap();
+		match &self.file {
+			Some(file) => write!(
+				out,
+				"<D:resourcetype/>\
+				<D:getcontentlength>{}</D:getcontentlength>\
+				<D:getcontenttype>{}</D:getcontenttype>",
+				file.len,
+				html_escape::encode_text(file.content_type.as_ref()),
+			)
+			.unwrap(),
+			None => out.push_str("<D:resourcetype><D:collection/></D:resourcetype>"),
+		}
+		out.push_str(
+			"</D:prop>\
+					<D:status>HTTP/1.1 200 OK</D:status>\
+				</D:propstat>\
+			</D:response>",
+		);
+	}
+}
+
+/// The requested properties are not parsed. All of the supported properties are always returned, as for `allprop`.
+async fn propfind(
+	headers: &HeaderMap,
+	config: &Config,
+	dir_sizes: &Arc<DirSizes>,
+	user_path: &Path,
+) -> Result<Response, ErrorResponse> {
+	// a missing header means infinite depth, which could be used to walk the whole tree in one request
+	let Some(include_children) = propfind_depth(headers) else {
+		return Err(
+			error::response(StatusCode::FORBIDDEN, "only a depth of 0 or 1 is supported").into(),
+		);
+	};
+
+	let relative_path = user_path.strip_prefix("/").unwrap();
+	let fs_path = crate::routes::resolve_path(config, relative_path).await?;
+	let metadata = tokio::fs::metadata(&fs_path)
+		.await
+		.map_err(io_ctx("reading metadata"))?;
+
+	let mut resources = vec![Resource {
+		href: encode_path(user_path)
+			+ if metadata.is_dir() && user_path != Path::new("/") {
+				"/"
+			} else {
+				""
+			},
+		name: user_path
+			.file_name()
+			.map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+		file: (!metadata.is_dir()).then(|| FileProps {
+			len: metadata.len(),
+			content_type: file::content_type(config, &fs_path),
+		}),
+		mtime: metadata.st_mtime(),
+	}];
+	if include_children && metadata.is_dir() {
+		let raw_entries = read_raw_entries(&fs_path, config)
+			.await
+			.map_err(io_ctx("reading directory"))?;
+		let loader = Loader {
+			config,
+			dir_sizes,
+			media: None,
+		};
+		resources.extend(
+			loader
+				.load_entries(raw_entries)
+				.await
+				.iter()
+				// they could not be served anyway
+				.filter(|entry| entry.problem().is_none())
+				.map(|entry| Resource::from_entry(config, user_path, entry)),
+		);
+	}
+
+	let mut body =
+		r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:">"#.to_owned();
+	let root = format!("{}/dav", config.base_path);
+	for resource in &resources {
+		resource.write(&mut body, &root);
+	}
+	body.push_str("</D:multistatus>");
+
+	Ok(
+		(
+			StatusCode::MULTI_STATUS,
+			[(CONTENT_TYPE, "application/xml; charset=utf-8")],
+			body,
+		)
+			.into_response(),
+	)
+}
+
+/// Whether the children of a folder are included, or `None` if the depth is not supported.
+fn propfind_depth(headers: &HeaderMap) -> Option<bool> {
+	match headers.get(DEPTH).map(http::HeaderValue::as_bytes) {
+		Some(b"0") => Some(false),
+		Some(b"1") => Some(true),
+		_ => None,
+	}
+}
+
+/// Dead properties are not stored, so every property in the update is refused and nothing is changed.
+async fn proppatch(
+	request: Request<Body>,
+	config: &Config,
+	user_path: &Path,
+) -> Result<Response, ErrorResponse> {
+	let relative_path = user_path.strip_prefix("/").unwrap();
+	let fs_path = crate::routes::resolve_path(config, relative_path).await?;
+	tokio::fs::metadata(&fs_path)
+		.await
+		.map_err(io_ctx("reading metadata"))?;
+
+	let mut body = Vec::new();
+	let mut stream = request.into_body();
+	while let Some(chunk) = stream.next().await {
+		let chunk = chunk.map_err(|error| error::BadRequest(error.to_string()))?;
+		if body.len() + chunk.len() > MAX_PROPPATCH_BYTES {
+			return Err(
+				error::response(
+					StatusCode::PAYLOAD_TOO_LARGE,
+					"the property update is too large",
+				)
+				.into(),
+			);
+		}
+		body.extend_from_slice(&chunk);
+	}
+	let body = std::str::from_utf8(&body)
+		.map_err(|_| error::BadRequest("the property update is not valid UTF-8"))?;
+	let properties =
+		proppatch_properties(body).ok_or(error::BadRequest("the property update is not valid"))?;
+
+	let mut response =
+		r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:"><D:response>"#
+			.to_owned();
+	write!(
+		response,
+		"<D:href>{}/dav{}</D:href><D:propstat><D:prop>",
+		html_escape::encode_text(&config.base_path),
+		html_escape::encode_text(&encode_path(user_path)),
+	)
+	.unwrap();
+	for (i, (namespace, name)) in properties.iter().enumerate() {
+		write!(
+			response,
+			r#"<p{i}:{name} xmlns:p{i}="{}"/>"#,
+			html_escape::encode_double_quoted_attribute(namespace),
+		)
+		.unwrap();
+	}
+	response.push_str(
+		"</D:prop><D:status>HTTP/1.1 403 Forbidden</D:status></D:propstat></D:response></D:multistatus>",
+	);
+
+	Ok(
+		(
+			StatusCode::MULTI_STATUS,
+			[(CONTENT_TYPE, "application/xml; charset=utf-8")],
+			response,
+		)
+			.into_response(),
+	)
+}
+
+/// The namespaces and names of the properties set or removed by a `propertyupdate`, or `None` if it is not one.
+fn proppatch_properties(body: &str) -> Option<Vec<(String, String)>> {
+	let document = roxmltree::Document::parse(body).ok()?;
+	let root = document.root_element();
+	if !root.has_tag_name(("DAV:", "propertyupdate")) {
+		return None;
+	}
+	let properties = root
+		.children()
+		.filter(|update| {
+			update.has_tag_name(("DAV:", "set")) || update.has_tag_name(("DAV:", "remove"))
+		})
+		.flat_map(|update| update.children())
+		.filter(|prop| prop.has_tag_name(("DAV:", "prop")))
+		.flat_map(|prop| prop.children())
+		.filter(roxmltree::Node::is_element)
+		.map(|property| {
+			let name = property.tag_name();
+			(
+				name.namespace().unwrap_or_default().to_owned(),
+				name.name().to_owned(),
+			)
+		})
+		.collect();
+	Some(properties)
+}
+
+async fn mkcol(
+	headers: &HeaderMap,
+	config: &Config,
+	client: &Client,
+	user_path: &Path,
+) -> Result<Response, ErrorResponse> {
+	let has_body = headers
+		.get(CONTENT_LENGTH)
+		.is_some_and(|length| length != "0");
+	if has_body {
+		return Err(
+			error::response(
+				StatusCode::UNSUPPORTED_MEDIA_TYPE,
+				"folders cannot be created with contents",
+			)
+			.into(),
+		);
+	}
+	let (Some(dir_path), Some(name)) = (
+		user_path.parent(),
+		user_path.file_name().and_then(std::ffi::OsStr::to_str),
+	) else {
+		return Err(error::response(StatusCode::METHOD_NOT_ALLOWED, "the root already exists").into());
+	};
+
+	manage::apply(
+		config,
+		client,
+		dir_path,
+		Action::Mkdir {
+			name: name.to_owned(),
+		},
+	)
+	.await?;
+	Ok(StatusCode::CREATED.into_response())
+}
+
+/// `COPY` if `copy`, otherwise `MOVE`.
+/// With `Overwrite: T`, which is the default, an existing destination is moved to the trash, so it is refused if there is none.
+async fn transfer(
+	copy: bool,
+	headers: &HeaderMap,
+	config: &Config,
+	client: &Client,
+	user_path: &Path,
+) -> Result<Response, ErrorResponse> {
+	let Some(destination) = headers
+		.get(DESTINATION)
+		.and_then(|destination| destination.to_str().ok())
+		.and_then(|destination| decode_destination(destination, &format!("{}/dav", config.base_path)))
+	else {
+		return Err(error::BadRequest("missing or invalid destination").into());
+	};
+	// replacing a folder that contains the source would trash the source along with it
+	if destination.starts_with(user_path) || user_path.starts_with(&destination) {
+		return Err(
+			error::response(StatusCode::FORBIDDEN, "the source and destination overlap").into(),
+		);
+	}
+	let overwrite = overwrite(headers).ok_or(error::BadRequest("`Overwrite` must be `T` or `F`"))?;
+	let kind = if copy {
+		let recursive = copy_depth(headers).ok_or(error::BadRequest(
+			"only a depth of 0 or infinity can be copied",
+		))?;
+		Transfer::Copy { recursive }
+	} else {
+		Transfer::Move
+	};
+
+	let replaced = manage::transfer(config, client, user_path, &destination, kind, overwrite).await?;
+	Ok(
+		if replaced {
+			StatusCode::NO_CONTENT
+		} else {
+			StatusCode::CREATED
+		}
+		.into_response(),
+	)
+}
+
+/// Whether an existing destination should be replaced, or `None` if the header is invalid.
+fn overwrite(headers: &HeaderMap) -> Option<bool> {
+	match headers.get(OVERWRITE).map(http::HeaderValue::as_bytes) {
+		None | Some(b"T" | b"t") => Some(true),
+		Some(b"F" | b"f") => Some(false),
+		Some(_) => None,
+	}
+}
+
+/// Whether the contents of a folder are copied along with it, or `None` if the depth is invalid for copying.
+fn copy_depth(headers: &HeaderMap) -> Option<bool> {
+	match headers.get(DEPTH).map(http::HeaderValue::as_bytes) {
+		None => Some(true),
+		Some(depth) if depth.eq_ignore_ascii_case(b"infinity") => Some(true),
+		Some(b"0") => Some(false),
+		Some(_) => None,
+	}
+}
+
+/// `Destination` is an absolute URL, or sometimes just a path, which must be within `root`.
+fn decode_destination(destination: &str, root: &str) -> Option<PathBuf> {
+	let path = match destination.split_once("://") {
+		Some((_scheme, rest)) => &rest[rest.find('/')?..],
+		None => destination,
+	};
+	let path = path.strip_prefix(root)?;
+	let path = path.trim_end_matches('/');
+	let decoded = percent_decode_str(path).decode_utf8().ok()?;
+	if !decoded.starts_with('/') {
+		return None;
+	}
+	Some(PathBuf::from(&*decoded))
+}
+
+pub fn configure() -> Router {
+	Router::new()
+		.route("/", any(handler))
+		.route("/*path", any(handler))
+}
+
+#[test]
+fn test_decode_destination() {
+	assert_eq!(
+		decode_destination("http://localhost:3000/dav/a%20b/c/", "/dav"),
+		Some(PathBuf::from("/a b/c"))
+	);
+	assert_eq!(
+		decode_destination("/dav/a", "/dav"),
+		Some(PathBuf::from("/a"))
+	);
+	assert_eq!(decode_destination("/dav", "/dav"), None);
+	assert_eq!(decode_destination("/davx/a", "/dav"), None);
+	assert_eq!(decode_destination("http://localhost/fs/a", "/dav"), None);
+	assert_eq!(
+		decode_destination("https://example.com/files/dav/a", "/files/dav"),
+		Some(PathBuf::from("/a"))
+	);
+	assert_eq!(
+		decode_destination("https://example.com/dav/a", "/files/dav"),
+		None
+	);
+}
+
+#[cfg(test)]
+fn headers(pairs: &[(HeaderName, &'static str)]) -> HeaderMap {
+	pairs
+		.iter()
+		.map(|(name, value)| (name.clone(), http::HeaderValue::from_static(value)))
+		.collect()
+}
+
+#[test]
+fn test_propfind_depth() {
+	assert_eq!(propfind_depth(&headers(&[(DEPTH, "0")])), Some(false));
+	assert_eq!(propfind_depth(&headers(&[(DEPTH, "1")])), Some(true));
+	assert_eq!(propfind_depth(&headers(&[(DEPTH, "infinity")])), None);
+	assert_eq!(propfind_depth(&headers(&[])), None);
+}
+
+#[test]
+fn test_transfer_headers() {
+	assert_eq!(overwrite(&headers(&[])), Some(true));
+	assert_eq!(overwrite(&headers(&[(OVERWRITE, "T")])), Some(true));
+	assert_eq!(overwrite(&headers(&[(OVERWRITE, "F")])), Some(false));
+	assert_eq!(overwrite(&headers(&[(OVERWRITE, "yes")])), None);
+
+	assert_eq!(copy_depth(&headers(&[])), Some(true));
+	assert_eq!(copy_depth(&headers(&[(DEPTH, "Infinity")])), Some(true));
+	assert_eq!(copy_depth(&headers(&[(DEPTH, "0")])), Some(false));
+	assert_eq!(copy_depth(&headers(&[(DEPTH, "1")])), None);
+}
+
+#[test]
+fn test_proppatch_properties() {
+	let body = r#"<?xml version="1.0"?>
+		<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:example">
+			<D:set><D:prop><Z:color>red</Z:color><D:displayname>x</D:displayname></D:prop></D:set>
+			<D:remove><D:prop><Z:size/></D:prop></D:remove>
+		</D:propertyupdate>"#;
+	assert_eq!(
+		proppatch_properties(body).unwrap(),
+		[
+			("urn:example", "color"),
+			("DAV:", "displayname"),
+			("urn:example", "size"),
+		]
+		.map(|(namespace, name)| (namespace.to_owned(), name.to_owned())),
+	);
+	assert_eq!(
+		proppatch_properties(r#"<D:propfind xmlns:D="DAV:"/>"#),
+		None
+	);
+	assert_eq!(proppatch_properties("<unclosed>"), None);
+}
+
+/// Files are written with their contents, and uploads are limited to 4 bytes.
+#[cfg(test)]
+fn test_tree(files: &[(&str, &str)], trash: bool) -> (PathBuf, Config) {
+	let root = std::env::temp_dir().join(format!("dexr-test-{:016x}", fastrand::u64(..)));
+	for (file, contents) in files {
+		let path = root.join(file);
+		std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+		std::fs::write(path, contents).unwrap();
+	}
+	let root = std::fs::canonicalize(root).unwrap();
+	let config = figment::Figment::from(figment::providers::Serialized::defaults(
+		serde_json::json!({
+			"address": "tcp://127.0.0.1:3000",
+			"index_root": root,
+			"thumbnail_tmp": root.join(".tmp"),
+			"writable": [{ "path": "/", "manage": true, "max_upload_bytes": 4 }],
+			"trash_dir": trash.then(|| root.join(".trash")),
+		}),
+	))
+	.extract()
+	.unwrap();
+	(root, config)
+}
+
+#[cfg(test)]
+async fn transfer_status(
+	config: &Config,
+	copy: bool,
+	pairs: &[(HeaderName, &'static str)],
+	from: &str,
+) -> StatusCode {
+	let result = transfer(
+		copy,
+		&headers(pairs),
+		config,
+		&Client::default(),
+		Path::new(from),
+	)
+	.await;
+	result.into_response().status()
+}
+
+#[tokio::test]
+async fn test_move_errors() {
+	let (root, config) = test_tree(&[("a/file", ""), ("b", "")], false);
+	assert_eq!(
+		transfer_status(&config, false, &[], "/b").await,
+		StatusCode::BAD_REQUEST
+	);
+	assert_eq!(
+		transfer_status(&config, false, &[(DESTINATION, "/dav/a/inner")], "/a").await,
+		StatusCode::FORBIDDEN
+	);
+	assert_eq!(
+		transfer_status(&config, false, &[(DESTINATION, "/dav/a")], "/a/file").await,
+		StatusCode::FORBIDDEN
+	);
+	assert_eq!(
+		transfer_status(
+			&config,
+			false,
+			&[(DESTINATION, "/dav/c"), (OVERWRITE, "maybe")],
+			"/b"
+		)
+		.await,
+		StatusCode::BAD_REQUEST
+	);
+	assert_eq!(
+		transfer_status(&config, false, &[(DESTINATION, "/dav/c")], "/missing").await,
+		StatusCode::NOT_FOUND
+	);
+	assert_eq!(
+		transfer_status(
+			&config,
+			false,
+			&[(DESTINATION, "/dav/a/file"), (OVERWRITE, "F")],
+			"/b"
+		)
+		.await,
+		StatusCode::PRECONDITION_FAILED
+	);
+	// replacing needs somewhere to put the old entry
+	assert_eq!(
+		transfer_status(&config, false, &[(DESTINATION, "/dav/a/file")], "/b").await,
+		StatusCode::FORBIDDEN
+	);
+	assert!(root.join("b").exists());
+	assert_eq!(
+		transfer_status(
+			&config,
+			false,
+			&[(DESTINATION, "/dav/c"), (OVERWRITE, "F")],
+			"/b"
+		)
+		.await,
+		StatusCode::CREATED
+	);
+	assert!(root.join("c").exists() && !root.join("b").exists());
+
+	std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_failed_transfer_keeps_destination() {
+	let (root, config) = test_tree(&[("a/file", "old"), ("big", "too large")], true);
+	assert_eq!(
+		transfer_status(&config, true, &[(DESTINATION, "/dav/a/file")], "/big").await,
+		StatusCode::PAYLOAD_TOO_LARGE
+	);
+	assert_eq!(std::fs::read_to_string(root.join("a/file")).unwrap(), "old");
+	assert!(!root.join(".trash").exists());
+
+	assert_eq!(
+		transfer_status(&config, false, &[(DESTINATION, "/dav/a/file")], "/big").await,
+		StatusCode::NO_CONTENT
+	);
+	assert_eq!(
+		std::fs::read_to_string(root.join("a/file")).unwrap(),
+		"too large"
+	);
+	assert_eq!(std::fs::read_dir(root.join(".trash")).unwrap().count(), 1);
+
+	std::fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,186 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::sync::Semaphore;
+
+use super::lru::Lru;
+
+/// The number of directories whose sizes are remembered.
+const CAPACITY: usize = 100_000;
+
+/// The maximum number of directory trees that are walked at once to compute their total size.
+const CONCURRENT_WALKS: usize = 4;
+
+/// Caches the number of items in directories, keyed by the modification time of the directory, and optionally their total recursive size.
+#[derive(Debug)]
+pub struct DirSizes {
+	cache: Mutex<Lru<PathBuf, Cached>>,
+	walks: Semaphore,
+}
+
+impl Default for DirSizes {
+	fn default() -> Self {
+		Self {
+			cache: Mutex::new(Lru::new(CAPACITY)),
+			walks: Semaphore::new(CONCURRENT_WALKS),
+		}
+	}
+}
+
+#[derive(Debug)]
+struct Cached {
+	mtime: SystemTime,
+	items: u64,
+	total: Option<Total>,
+	computing_total: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Total {
+	bytes: u64,
+	computed_at: Instant,
+	/// Whether the directory has changed since, so it is stale regardless of its age.
+	changed: bool,
+}
+
+impl DirSizes {
+	pub(super) async fn items(
+		&self,
+		path: &Path,
+		mtime: SystemTime,
+		exclude_dotfiles: bool,
+	) -> std::io::Result<u64> {
+		if let Some(cached) = self.cache.lock().unwrap().get(path) {
+			if cached.mtime == mtime {
+				return Ok(cached.items);
+			}
+		}
+
+		let items = count_items(path, exclude_dotfiles).await?;
+
+		let mut cache = self.cache.lock().unwrap();
+		let previous = cache.get(path);
+		let computing_total = previous.is_some_and(|cached| cached.computing_total);
+		// kept so that it can still be shown until it has been computed again
+		let total = previous.and_then(|cached| cached.total).map(|total| Total {
+			changed: true,
+			..total
+		});
+		cache.insert(
+			path.to_owned(),
+			Cached {
+				mtime,
+				items,
+				total,
+				computing_total,
+			},
+		);
+
+		Ok(items)
+	}
+
+	/// Get the total size of the files in the directory, recursively.
+	///
+	/// If the size is not known, is older than `max_age`, or the directory has changed since, it is computed in the background so it can be shown in later listings.
+	/// In the meantime, the stale size is returned if there is one.
+	/// Must be called after `items` for the same path.
+	pub(super) fn total_bytes(
+		self: &Arc<Self>,
+		path: &Path,
+		exclude_dotfiles: bool,
+		max_age: Duration,
+	) -> Option<u64> {
+		let mut cache = self.cache.lock().unwrap();
+		let cached = cache.get_mut(path)?;
+		let total = cached.total;
+
+		let stale = total.is_none_or(|total| total.changed || total.computed_at.elapsed() > max_age);
+		if stale && !cached.computing_total {
+			cached.computing_total = true;
+			let mtime = cached.mtime;
+			tokio::spawn(Arc::clone(self).compute_total(path.to_owned(), mtime, exclude_dotfiles));
+		}
+
+		total.map(|total| total.bytes)
+	}
+
+	/// `mtime` is that of the directory when the walk started.
+	async fn compute_total(
+		self: Arc<Self>,
+		path: PathBuf,
+		mtime: SystemTime,
+		exclude_dotfiles: bool,
+	) {
+		let permit = self.walks.acquire().await.unwrap();
+		let bytes = tokio::task::spawn_blocking({
+			let path = path.clone();
+			move || walk_total(&path, exclude_dotfiles)
+		})
+		.await;
+		drop(permit);
+
+		let bytes = match bytes {
+			Ok(bytes) => bytes,
+			Err(error) => std::panic::resume_unwind(error.into_panic()), /* assume that the task was not cancelled. */
+		};
+
+		let mut cache = self.cache.lock().unwrap();
+		if let Some(cached) = cache.get_mut(&path) {
+			cached.computing_total = false;
+			cached.total = Some(Total {
+				bytes,
+				computed_at: Instant::now(),
+				// the walk could have missed a change that happened during it
+				changed: cached.mtime != mtime,
+			});
+		}
+	}
+}
+
+async fn count_items(path: &Path, exclude_dotfiles: bool) -> std::io::Result<u64> {
+	let mut entries = tokio::fs::read_dir(path).await?;
+	let mut count = 0;
+	while let Some(entry) = entries.next_entry().await? {
+		if !(exclude_dotfiles && crate::routes::starts_with_dot(&entry.file_name())) {
+			count += 1;
+		}
+	}
+	Ok(count)
+}
+
+/// Symlinks are not followed, to avoid counting files multiple times and getting stuck in loops.
+/// Unreadable subdirectories are skipped.
+fn walk_total(root: &Path, exclude_dotfiles: bool) -> u64 {
+	let mut total = 0;
+	let mut stack = vec![root.to_owned()];
+
+	while let Some(dir) = stack.pop() {
+		let entries = match std::fs::read_dir(&dir) {
+			Ok(entries) => entries,
+			Err(error) => {
+				tracing::debug!(
+					?dir,
+					"skipping unreadable directory while computing size: {error}"
+				);
+				continue;
+			}
+		};
+
+		for entry in entries.flatten() {
+			if exclude_dotfiles && crate::routes::starts_with_dot(&entry.file_name()) {
+				continue;
+			}
+			let Ok(metadata) = entry.metadata() else {
+				continue;
+			};
+			if metadata.is_dir() {
+				stack.push(entry.path());
+			} else if metadata.is_file() {
+				total += metadata.len();
+			}
+		}
+	}
+
+	total
+}
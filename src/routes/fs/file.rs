@@ -0,0 +1,97 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::response::{IntoResponse as _, Response};
+use http::header::{CONTENT_DISPOSITION, CONTENT_SECURITY_POLICY};
+use http::{HeaderValue, Request};
+use hyper::service::Service as _;
+use hyper::Body;
+
+use crate::config::{ActiveContent, Config};
+use crate::routes::limits::{self, Limiters};
+
+/// Serve a file as is, rather than listing it.
+/// `ServeFile` handles ranges as well as `Last-Modified` and `If-Modified-Since`.
+pub(super) async fn send(
+	request: Request<Body>,
+	config: &Config,
+	fs_path: PathBuf,
+	download: bool,
+) -> Response {
+	let mime = content_type(config, &fs_path);
+	let active = is_active_content(&mime);
+	let attachment =
+		download || (active && matches!(config.active_content, ActiveContent::Attachment));
+	let disposition = fs_path
+		.file_name()
+		.map(|name| content_disposition(attachment, &name.to_string_lossy()));
+
+	let limiters = Arc::clone(request.extensions().get::<Arc<Limiters>>().unwrap());
+	let mut response = tower_http::services::ServeFile::new_with_mime(fs_path, &mime)
+		.call(request)
+		.await
+		.unwrap_or_else(|never| match never {})
+		.into_response();
+
+	let headers = response.headers_mut();
+	if let Some(disposition) = disposition {
+		headers.insert(CONTENT_DISPOSITION, disposition);
+	}
+	if active && !attachment {
+		// a unique origin without scripts, so the file cannot act on behalf of the index
+		headers.insert(CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox"));
+	}
+	limits::throttle(response, config, limiters)
+}
+
+/// From the configured overrides if present, otherwise guessed from the extension.
+pub(super) fn content_type(config: &Config, fs_path: &std::path::Path) -> mime::Mime {
+	crate::markup::lowercase_extension(fs_path)
+		.and_then(|extension| config.mime_types.get(&extension).cloned())
+		.unwrap_or_else(|| mime_guess::from_path(fs_path).first_or_octet_stream())
+}
+
+/// Whether browsers would run scripts embedded in the file.
+fn is_active_content(mime: &mime::Mime) -> bool {
+	matches!(
+		mime.essence_str(),
+		"text/html" | "application/xhtml+xml" | "image/svg+xml" | "text/xml" | "application/xml"
+	)
+}
+
+/// Includes both a plain `filename` for old clients and an RFC 5987 `filename*` for the exact UTF-8 name.
+fn content_disposition(attachment: bool, name: &str) -> HeaderValue {
+	let mut value = if attachment { "attachment" } else { "inline" }.to_owned();
+
+	value.push_str("; filename=\"");
+	value.extend(name.chars().map(|ch| {
+		if ch.is_ascii() && !ch.is_ascii_control() && ch != '"' && ch != '\\' {
+			ch
+		} else {
+			'_'
+		}
+	}));
+	value.push_str("\"; filename*=UTF-8''");
+	for &byte in name.as_bytes() {
+		if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+			value.push(char::from(byte));
+		} else {
+			write!(value, "%{byte:02X}").unwrap();
+		}
+	}
+
+	HeaderValue::from_str(&value).unwrap()
+}
+
+#[test]
+fn test_content_disposition() {
+	assert_eq!(
+		content_disposition(true, "report.pdf"),
+		"attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+	);
+	assert_eq!(
+		content_disposition(false, "naïve \"plan\".txt"),
+		"inline; filename=\"na_ve _plan_.txt\"; filename*=UTF-8''na%C3%AFve%20%22plan%22.txt"
+	);
+}
@@ -0,0 +1,79 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A map that forgets the least recently used entries once it holds more than `capacity` of them.
+#[derive(Debug)]
+pub(super) struct Lru<K, V> {
+	entries: HashMap<K, (V, u64)>,
+	capacity: usize,
+	/// Incremented on every use, so that entries can be ordered by when they were last used.
+	clock: u64,
+}
+
+impl<K: Hash + Eq, V> Lru<K, V> {
+	pub(super) fn new(capacity: usize) -> Self {
+		Self {
+			entries: HashMap::new(),
+			capacity: capacity.max(1),
+			clock: 0,
+		}
+	}
+
+	fn tick(&mut self) -> u64 {
+		self.clock += 1;
+		self.clock
+	}
+
+	pub(super) fn get<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<&V>
+	where
+		K: Borrow<Q>,
+	{
+		self.get_mut(key).map(|value| &*value)
+	}
+
+	pub(super) fn get_mut<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+	where
+		K: Borrow<Q>,
+	{
+		let now = self.tick();
+		let (value, used) = self.entries.get_mut(key)?;
+		*used = now;
+		Some(value)
+	}
+
+	pub(super) fn insert(&mut self, key: K, value: V) {
+		let now = self.tick();
+		self.entries.insert(key, (value, now));
+		if self.entries.len() > self.capacity {
+			self.evict();
+		}
+	}
+
+	/// Forget a quarter of the capacity at once, so that the cost of finding the oldest entries is spread over many inserts.
+	fn evict(&mut self) {
+		let excess = self.entries.len() - self.capacity + (self.capacity / 4).max(1);
+		let mut uses: Vec<u64> = self.entries.values().map(|&(_, used)| used).collect();
+		let (_, &mut newest_evicted, _) = uses.select_nth_unstable(excess - 1);
+		self
+			.entries
+			.retain(|_, &mut (_, used)| used > newest_evicted);
+	}
+}
+
+#[test]
+fn test_lru() {
+	let mut lru = Lru::new(4);
+	for key in 0..4 {
+		lru.insert(key, key);
+	}
+	// used, so it is kept
+	assert_eq!(lru.get(&0), Some(&0));
+	lru.insert(4, 4);
+	// a quarter of the capacity is evicted along with the excess
+	assert_eq!(lru.get(&1), None);
+	assert_eq!(lru.get(&2), None);
+	assert_eq!(lru.get(&0), Some(&0));
+	assert_eq!(lru.get(&3), Some(&3));
+	assert_eq!(lru.get(&4), Some(&4));
+}
@@ -0,0 +1,536 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use axum::extract;
+use axum::response::{ErrorResponse, Response};
+use http::StatusCode;
+use serde::Deserialize;
+
+use super::audit::{self, Client, Record};
+use super::upload::{check_name, redirect_to, rename_no_replace, target_dir, temp_name};
+use crate::config::{Config, Writable};
+use crate::error::{self, io_ctx};
+
+/// Sent as a form to the URL of the entry, or of the directory for `mkdir`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub(super) enum Action {
+	/// Create a folder called `name` in the directory.
+	Mkdir { name: String },
+	/// Give the entry a new name in the same directory.
+	Rename { name: String },
+	/// Move the entry into the directory at `destination`, as in URLs.
+	Move { destination: PathBuf },
+	/// Move the entry to the trash.
+	Delete,
+}
+
+/// Redirects to the listing of the directory that was changed.
+pub(super) async fn post(
+	extract::Path(user_path): extract::Path<PathBuf>,
+	extract::Extension(config): extract::Extension<Arc<Config>>,
+	client: Client,
+	extract::Form(action): extract::Form<Action>,
+) -> Result<Response, ErrorResponse> {
+	let changed_dir = apply(&config, &client, &user_path, action).await?;
+	Ok(redirect_to(&config, changed_dir))
+}
+
+/// Returns the directory that was changed, as in URLs.
+pub(super) async fn apply<'a>(
+	config: &Config,
+	client: &Client,
+	user_path: &'a Path,
+	action: Action,
+) -> Result<&'a Path, ErrorResponse> {
+	crate::routes::assert_path_safe(user_path)?;
+	if config.exclude_dotfiles && crate::routes::is_hidden_path(user_path) {
+		return Err(error::NotFound.into());
+	}
+
+	Ok(match action {
+		Action::Mkdir { name } => {
+			mkdir(config, client, user_path, &name).await?;
+			user_path
+		}
+		Action::Rename { name } => {
+			// otherwise a name with slashes would move the entry elsewhere
+			check_name(config, &name)?;
+			let target = Target::find(config, user_path).await?;
+			move_to(config, client, &target, &target.parent_path.join(name)).await?;
+			target.parent_path
+		}
+		Action::Move { destination } => {
+			let target = Target::find(config, user_path).await?;
+			move_to(config, client, &target, &destination.join(target.name)).await?;
+			target.parent_path
+		}
+		Action::Delete => {
+			let target = Target::find(config, user_path).await?;
+			delete(config, client, &target).await?;
+			target.parent_path
+		}
+	})
+}
+
+/// An existing entry in a directory that can be managed.
+pub(super) struct Target<'a> {
+	/// As in URLs.
+	user_path: &'a Path,
+	/// As in URLs.
+	parent_path: &'a Path,
+	name: &'a str,
+	fs_path: PathBuf,
+}
+
+impl<'a> Target<'a> {
+	pub(super) async fn find(
+		config: &Config,
+		user_path: &'a Path,
+	) -> Result<Target<'a>, ErrorResponse> {
+		Self::find_existing(config, user_path)
+			.await?
+			.ok_or_else(|| error::NotFound.into())
+	}
+
+	/// Like `find`, but `None` if there is no entry at `user_path`, as long as it could be created.
+	pub(super) async fn find_existing(
+		config: &Config,
+		user_path: &'a Path,
+	) -> Result<Option<Target<'a>>, ErrorResponse> {
+		let (Some(parent_path), Some(name)) = (
+			user_path.parent(),
+			user_path.file_name().and_then(std::ffi::OsStr::to_str),
+		) else {
+			return Err(error::BadRequest("the root cannot be changed").into());
+		};
+		let (parent, _) = managed_dir(config, parent_path).await?;
+		// the entry itself is changed, even if it is a symlink
+		let fs_path = parent.join(name);
+		match tokio::fs::symlink_metadata(&fs_path).await {
+			Ok(_) => {}
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			Err(error) => return Err(io_ctx("reading metadata of entry")(error).into()),
+		}
+		Ok(Some(Target {
+			user_path,
+			parent_path,
+			name,
+			fs_path,
+		}))
+	}
+}
+
+/// Like the directories that can be uploaded to, but also requiring `manage`.
+async fn managed_dir<'a>(
+	config: &'a Config,
+	dir_path: &Path,
+) -> Result<(PathBuf, &'a Writable), ErrorResponse> {
+	let (dir, writable) = target_dir(config, dir_path).await?;
+	if !writable.manage {
+		return Err(
+			error::response(StatusCode::FORBIDDEN, "changing files is not allowed here").into(),
+		);
+	}
+	Ok((dir, writable))
+}
+
+async fn mkdir(
+	config: &Config,
+	client: &Client,
+	dir_path: &Path,
+	name: &str,
+) -> Result<(), ErrorResponse> {
+	check_name(config, name)?;
+	let (dir, _) = managed_dir(config, dir_path).await?;
+	match tokio::fs::create_dir(dir.join(name)).await {
+		Ok(()) => {}
+		Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => return Err(exists().into()),
+		Err(error) => return Err(io_ctx("creating folder")(error).into()),
+	}
+
+	let path = dir_path.join(name);
+	audit::record(
+		config,
+		Record {
+			action: "mkdir",
+			path: &path,
+			destination: None,
+			client,
+		},
+	)
+	.await;
+	Ok(())
+}
+
+/// Move the entry to `new_path`, as in URLs, which must be in a directory that can be managed.
+/// This is a rename if it stays in the same directory.
+pub(super) async fn move_to(
+	config: &Config,
+	client: &Client,
+	target: &Target<'_>,
+	new_path: &Path,
+) -> Result<(), ErrorResponse> {
+	let (new_parent, _, new_name) = destination(config, target.user_path, new_path).await?;
+	rename_new(&target.fs_path, &new_parent.join(new_name)).await?;
+
+	let action = if new_path.parent() == Some(target.parent_path) {
+		"rename"
+	} else {
+		"move"
+	};
+	moved(config, client, action, target.user_path, new_path).await;
+	Ok(())
+}
+
+/// How `transfer` changes the entry.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Transfer {
+	Move,
+	/// The contents of folders are only copied if `recursive`.
+	Copy {
+		recursive: bool,
+	},
+}
+
+/// Move or copy the entry at `user_path` to `new_path`, both as in URLs, which must be in a directory that can be managed.
+/// An existing entry at `new_path` is moved to the trash if `overwrite`, but only once nothing else can fail, and it is put back if the entry cannot be moved into its place.
+/// Returns whether an existing entry was replaced.
+pub(super) async fn transfer(
+	config: &Config,
+	client: &Client,
+	user_path: &Path,
+	new_path: &Path,
+	kind: Transfer,
+	overwrite: bool,
+) -> Result<bool, ErrorResponse> {
+	let (new_parent, writable, new_name) = destination(config, user_path, new_path).await?;
+	let existing = Target::find_existing(config, new_path).await?;
+	if existing.is_some() {
+		if !overwrite {
+			return Err(
+				error::response(
+					StatusCode::PRECONDITION_FAILED,
+					"the destination already exists",
+				)
+				.into(),
+			);
+		}
+		if config.trash_dir.is_none() {
+			return Err(error::response(StatusCode::FORBIDDEN, "deleting is disabled").into());
+		}
+	}
+
+	// everything that can fail before the destination is replaced
+	let (source, target) = match kind {
+		Transfer::Move => {
+			let target = Target::find(config, user_path).await?;
+			same_filesystem(&target.fs_path, &new_parent).await?;
+			(target.fs_path.clone(), Some(target))
+		}
+		Transfer::Copy { recursive } => {
+			let temp_path = copy_to_temp(config, user_path, &new_parent, writable, recursive).await?;
+			(temp_path, None)
+		}
+	};
+	let trashed = match &existing {
+		Some(existing) => match trash(config, existing).await {
+			Ok(trashed) => Some(trashed),
+			Err(error) => {
+				if target.is_none() {
+					remove_tree(&source).await;
+				}
+				return Err(error);
+			}
+		},
+		None => None,
+	};
+
+	if let Err(error) = rename_new(&source, &new_parent.join(new_name)).await {
+		if target.is_none() {
+			remove_tree(&source).await;
+		}
+		if let (Some(existing), Some(trashed)) = (&existing, &trashed) {
+			if let Err(restore_error) = rename_new(trashed, &existing.fs_path).await {
+				tracing::error!(
+					?trashed,
+					to = ?existing.fs_path,
+					"could not restore replaced entry from trash: {restore_error:?}"
+				);
+			}
+		}
+		return Err(error);
+	}
+
+	if let (Some(existing), Some(trashed)) = (&existing, &trashed) {
+		deleted(config, client, existing, trashed).await;
+	}
+	match &target {
+		Some(target) => {
+			let action = if new_path.parent() == Some(target.parent_path) {
+				"rename"
+			} else {
+				"move"
+			};
+			moved(config, client, action, target.user_path, new_path).await;
+		}
+		None => {
+			audit::record(
+				config,
+				Record {
+					action: "copy",
+					path: user_path,
+					destination: Some(new_path),
+					client,
+				},
+			)
+			.await;
+		}
+	}
+	Ok(existing.is_some())
+}
+
+/// Renaming cannot move entries between filesystems, so this is refused before anything is changed.
+async fn same_filesystem(entry: &Path, new_parent: &Path) -> Result<(), ErrorResponse> {
+	use std::os::unix::fs::MetadataExt as _;
+
+	let entry = tokio::fs::symlink_metadata(entry)
+		.await
+		.map_err(io_ctx("reading metadata of entry"))?;
+	let new_parent = tokio::fs::metadata(new_parent)
+		.await
+		.map_err(io_ctx("reading metadata of destination"))?;
+	if entry.dev() != new_parent.dev() {
+		return Err(other_filesystem().into());
+	}
+	Ok(())
+}
+
+fn other_filesystem() -> Response {
+	error::response(
+		StatusCode::BAD_GATEWAY,
+		"entries cannot be moved to another filesystem",
+	)
+}
+
+/// Copy the entry at `user_path`, as in URLs, to a temporary entry in `new_parent`, so that it never appears partially copied.
+/// Unlike moving, the entry only has to be readable. The copied files count against the `max_upload_bytes` of the destination.
+async fn copy_to_temp(
+	config: &Config,
+	user_path: &Path,
+	new_parent: &Path,
+	writable: &Writable,
+	recursive: bool,
+) -> Result<PathBuf, ErrorResponse> {
+	crate::routes::assert_path_safe(user_path)?;
+	if config.exclude_dotfiles && crate::routes::is_hidden_path(user_path) {
+		return Err(error::NotFound.into());
+	}
+	let relative_path = user_path.strip_prefix("/").unwrap();
+	let source = crate::routes::resolve_path(config, relative_path).await?;
+	// the source could be a symlink to a folder above the destination
+	let canonical_source = tokio::fs::canonicalize(&source)
+		.await
+		.map_err(io_ctx("canonicalizing entry"))?;
+	if new_parent.starts_with(&canonical_source) {
+		return Err(error::BadRequest("a folder cannot be copied into itself").into());
+	}
+
+	let max_bytes = writable.max_upload_bytes;
+	let temp_path = new_parent.join(temp_name());
+	let copied = tokio::task::spawn_blocking({
+		let temp_path = temp_path.clone();
+		move || {
+			if tree_size(&source, recursive)? > max_bytes {
+				return Ok(false);
+			}
+			copy_tree(&source, &temp_path, recursive)?;
+			Ok::<_, std::io::Error>(true)
+		}
+	})
+	.await
+	.unwrap_or_else(|error| std::panic::resume_unwind(error.into_panic()))
+	.map_err(io_ctx("copying entry"));
+	match copied {
+		Ok(true) => Ok(temp_path),
+		Ok(false) => {
+			Err(error::response(StatusCode::PAYLOAD_TOO_LARGE, "the copy would be too large").into())
+		}
+		Err(error) => {
+			remove_tree(&temp_path).await;
+			Err(error.into())
+		}
+	}
+}
+
+/// Check that the entry at `user_path` can be moved or copied to `new_path`, both as in URLs, and find the canonical directory that it would be in.
+async fn destination<'a, 'b>(
+	config: &'a Config,
+	user_path: &Path,
+	new_path: &'b Path,
+) -> Result<(PathBuf, &'a Writable, &'b str), ErrorResponse> {
+	crate::routes::assert_path_safe(new_path)?;
+	if config.exclude_dotfiles && crate::routes::is_hidden_path(new_path) {
+		return Err(error::NotFound.into());
+	}
+	let (Some(new_parent_path), Some(new_name)) = (
+		new_path.parent(),
+		new_path.file_name().and_then(std::ffi::OsStr::to_str),
+	) else {
+		return Err(error::BadRequest("invalid file name").into());
+	};
+	check_name(config, new_name)?;
+	if new_parent_path.starts_with(user_path) {
+		return Err(error::BadRequest("a folder cannot be moved or copied into itself").into());
+	}
+
+	let (new_parent, writable) = managed_dir(config, new_parent_path).await?;
+	Ok((new_parent, writable, new_name))
+}
+
+/// The total size of the files that `copy_tree` would copy.
+fn tree_size(path: &Path, recursive: bool) -> std::io::Result<u64> {
+	let metadata = std::fs::symlink_metadata(path)?;
+	if metadata.is_file() {
+		return Ok(metadata.len());
+	}
+	if !(recursive && metadata.is_dir()) {
+		return Ok(0);
+	}
+	let mut total = 0;
+	for entry in std::fs::read_dir(path)? {
+		total += tree_size(&entry?.path(), true)?;
+	}
+	Ok(total)
+}
+
+/// Symlinks are copied as links rather than followed, and other special files are left out.
+fn copy_tree(from: &Path, to: &Path, recursive: bool) -> std::io::Result<()> {
+	let file_type = std::fs::symlink_metadata(from)?.file_type();
+	if file_type.is_symlink() {
+		std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+	} else if file_type.is_dir() {
+		std::fs::create_dir(to)?;
+		if recursive {
+			for entry in std::fs::read_dir(from)? {
+				let entry = entry?;
+				copy_tree(&entry.path(), &to.join(entry.file_name()), true)?;
+			}
+		}
+		Ok(())
+	} else if file_type.is_file() {
+		std::fs::copy(from, to).map(drop)
+	} else {
+		Ok(())
+	}
+}
+
+/// Failures are only logged, since this is cleaning up after another failure.
+async fn remove_tree(path: &Path) {
+	let result = match tokio::fs::symlink_metadata(path).await {
+		Ok(metadata) if metadata.is_dir() => tokio::fs::remove_dir_all(path).await,
+		Ok(_) => tokio::fs::remove_file(path).await,
+		Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+		Err(error) => Err(error),
+	};
+	if let Err(error) = result {
+		tracing::warn!(?path, "could not remove partial copy: {error}");
+	}
+}
+
+/// Rename, but never replace an existing entry.
+async fn rename_new(from: &Path, to: &Path) -> Result<(), ErrorResponse> {
+	match rename_no_replace(from, to).await {
+		Ok(()) => Ok(()),
+		Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => Err(exists().into()),
+		Err(error) => Err(io_ctx("moving entry")(error).into()),
+	}
+}
+
+fn exists() -> Response {
+	error::response(
+		StatusCode::CONFLICT,
+		"an entry with that name already exists",
+	)
+}
+
+/// Update the thumbnail cache and the audit log after an entry was renamed or moved.
+async fn moved(config: &Config, client: &Client, action: &'static str, from: &Path, to: &Path) {
+	relocate_thumbnails(config, from, Some(to)).await;
+	audit::record(
+		config,
+		Record {
+			action,
+			path: from,
+			destination: Some(to),
+			client,
+		},
+	)
+	.await;
+}
+
+async fn delete(
+	config: &Config,
+	client: &Client,
+	target: &Target<'_>,
+) -> Result<(), ErrorResponse> {
+	let trashed = trash(config, target).await?;
+	deleted(config, client, target, &trashed).await;
+	Ok(())
+}
+
+/// Returns where the entry was moved to.
+async fn trash(config: &Config, target: &Target<'_>) -> Result<PathBuf, ErrorResponse> {
+	static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+	let Some(trash_dir) = &config.trash_dir else {
+		return Err(error::response(StatusCode::FORBIDDEN, "deleting is disabled").into());
+	};
+	tokio::fs::create_dir_all(trash_dir)
+		.await
+		.map_err(io_ctx("ensuring existence of trash directory"))?;
+	// prefixed so that entries with the same name do not collide
+	let trashed = trash_dir.join(format!(
+		"{}.{}.{}",
+		time::OffsetDateTime::now_utc().unix_timestamp(),
+		COUNTER.fetch_add(1, Ordering::Relaxed),
+		target.name,
+	));
+	match tokio::fs::rename(&target.fs_path, &trashed).await {
+		Ok(()) => Ok(trashed),
+		// checked at startup, but the directories could have been mounted elsewhere since
+		Err(error) if error.kind() == std::io::ErrorKind::CrossesDevices => Err(
+			error::response(
+				StatusCode::INTERNAL_SERVER_ERROR,
+				"the trash is on another filesystem, so nothing can be deleted",
+			)
+			.into(),
+		),
+		Err(error) => Err(io_ctx("moving entry to trash")(error).into()),
+	}
+}
+
+/// Update the thumbnail cache and the audit log after an entry was moved to the trash.
+async fn deleted(config: &Config, client: &Client, target: &Target<'_>, trashed: &Path) {
+	relocate_thumbnails(config, target.user_path, None).await;
+	audit::record(
+		config,
+		Record {
+			action: "delete",
+			path: target.user_path,
+			destination: Some(trashed),
+			client,
+		},
+	)
+	.await;
+}
+
+/// Failures are only logged, since the entry has already been moved.
+async fn relocate_thumbnails(config: &Config, from: &Path, to: Option<&Path>) {
+	let relative = |path: &Path| path.strip_prefix("/").unwrap().to_owned();
+	let to = to.map(relative);
+	if let Err(error) = crate::thumbnail::relocate(config, &relative(from), to.as_deref()).await {
+		tracing::warn!(?from, ?to, "could not update thumbnail cache: {error}");
+	}
+}
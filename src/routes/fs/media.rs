@@ -0,0 +1,67 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::lru::Lru;
+use super::RichType;
+use crate::thumbnail::MediaInfo;
+
+/// The number of files whose media information is remembered.
+const CAPACITY: usize = 100_000;
+
+/// How long a single listing can spend probing files that are not cached yet.
+const PROBE_TIME: Duration = Duration::from_secs(10);
+
+/// Caches the media information of files, keyed by their modification time, since probing them can take seconds each.
+#[derive(Debug)]
+pub struct MediaCache {
+	cache: Mutex<Lru<PathBuf, (SystemTime, MediaInfo)>>,
+}
+
+impl Default for MediaCache {
+	fn default() -> Self {
+		Self {
+			cache: Mutex::new(Lru::new(CAPACITY)),
+		}
+	}
+}
+
+/// Probes files for a single listing, until its time is up.
+pub(super) struct MediaProbe<'a> {
+	cache: &'a MediaCache,
+	deadline: Instant,
+}
+
+impl<'a> MediaProbe<'a> {
+	pub(super) fn new(cache: &'a MediaCache) -> Self {
+		Self {
+			cache,
+			deadline: Instant::now() + PROBE_TIME,
+		}
+	}
+
+	/// Once the time is up, files that are not cached are left unknown, so they are sorted as such until a later listing probes them.
+	pub(super) async fn get(&self, path: &Path, mtime: SystemTime, ty: RichType) -> MediaInfo {
+		if let Some(&(cached_mtime, info)) = self.cache.cache.lock().unwrap().get(path) {
+			if cached_mtime == mtime {
+				return info;
+			}
+		}
+		if Instant::now() >= self.deadline {
+			return MediaInfo::default();
+		}
+
+		let info = tokio_rayon::spawn({
+			let path = path.to_owned();
+			move || crate::thumbnail::probe(&path, ty)
+		})
+		.await;
+		self
+			.cache
+			.cache
+			.lock()
+			.unwrap()
+			.insert(path.to_owned(), (mtime, info));
+		info
+	}
+}
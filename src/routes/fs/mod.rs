@@ -1,31 +1,69 @@
+use std::cmp::Ordering;
 use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
 use std::os::linux::fs::MetadataExt as _;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::extract;
+use axum::handler::Handler as _;
 use axum::response::{ErrorResponse, IntoResponse, Response};
 use axum::routing::{get, Router};
-use futures::stream::{poll_fn, FuturesUnordered};
-use futures::{FutureExt as _, TryStreamExt as _};
+use futures::StreamExt as _;
 use http::Request;
-use hyper::service::Service as _;
 use hyper::Body;
 use serde::{Deserialize, Serialize};
 
-use crate::config::Config;
+use self::collation::Names;
+use self::media::MediaProbe;
+use super::caching::{set_cache_control, Validators};
+use super::Mount;
+use crate::config::{Config, Symlinks};
 use crate::error::{self, io_ctx};
 use crate::thumbnail::Type as RichType;
-
+use crate::util::join_paths;
+
+mod audit;
+mod collation;
+pub(super) mod dav;
+mod dir_sizes;
+mod file;
+mod lru;
+mod manage;
+mod media;
+mod readme;
 mod template;
+mod upload;
+
+pub(super) use dir_sizes::DirSizes;
+pub(super) use media::MediaCache;
+pub(super) use template::write_document_start;
 
 impl SortBy {
-	fn compare(self, a: &Entry, b: &Entry) -> std::cmp::Ordering {
+	fn compare(self, a: &Entry, b: &Entry, names: &Names) -> Ordering {
 		match self {
-			Self::Name => a.name.cmp(&b.name),
+			Self::Name => names.compare(&a.name, &b.name),
 			Self::Size => a.size.cmp(&b.size),
 			Self::MTime => a.mtime.cmp(&b.mtime),
+			Self::Type => match (a.extension(), b.extension()) {
+				(Some(a), Some(b)) => a
+					.bytes()
+					.map(|byte| byte.to_ascii_lowercase())
+					.cmp(b.bytes().map(|byte| byte.to_ascii_lowercase())),
+				(a, b) => a.is_some().cmp(&b.is_some()),
+			},
+			Self::Captured => a.media.captured.cmp(&b.media.captured),
+			Self::Dimensions => a.media.pixels().cmp(&b.media.pixels()),
+			Self::Duration => a.media.duration.cmp(&b.media.duration),
 		}
+		// many entries can share the same key, so fall back to the name to keep the order stable
+		.then_with(|| names.compare(&a.name, &b.name))
+	}
+
+	/// Whether entries have to be probed with `thumbnail::probe` to sort by this key.
+	fn needs_media(self) -> bool {
+		matches!(self, Self::Captured | Self::Dimensions | Self::Duration)
 	}
 }
 
@@ -36,6 +74,10 @@ pub enum SortBy {
 	Name,
 	Size,
 	MTime,
+	Type,
+	Captured,
+	Dimensions,
+	Duration,
 }
 
 #[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
@@ -53,14 +95,23 @@ impl SortOrder {
 			Self::Descending => Self::Ascending,
 		}
 	}
+
+	fn apply(self, ordering: Ordering) -> Ordering {
+		match self {
+			Self::Ascending => ordering,
+			Self::Descending => ordering.reverse(),
+		}
+	}
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct Sorting {
 	#[serde(default, rename = "sort_by")]
 	by: SortBy,
 	#[serde(default, rename = "sort_order")]
 	order: SortOrder,
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	dirs_first: bool,
 }
 
 impl Sorting {
@@ -68,7 +119,7 @@ impl Sorting {
 		(self.link_for(for_column), self.class_for(for_column))
 	}
 
-	fn link_for(self, for_column: SortBy) -> impl Display {
+	fn link(self) -> impl Display {
 		struct Helper(Sorting);
 
 		impl Display for Helper {
@@ -77,15 +128,17 @@ impl Sorting {
 			}
 		}
 
+		Helper(self)
+	}
+
+	fn link_for(self, for_column: SortBy) -> impl Display {
 		let by = for_column;
 		let order = if self.by == for_column {
 			self.order.reverse()
 		} else {
 			SortOrder::default()
 		};
-		let new_sorting = Self { by, order };
-
-		Helper(new_sorting)
+		Self { by, order, ..self }.link()
 	}
 
 	fn class_for(self, for_column: SortBy) -> &'static str {
@@ -98,40 +151,103 @@ impl Sorting {
 			""
 		}
 	}
+
+	fn dirs_first_link(self) -> impl Display {
+		Self {
+			dirs_first: !self.dirs_first,
+			..self
+		}
+		.link()
+	}
+
+	fn dirs_first_class(self) -> &'static str {
+		if self.dirs_first {
+			"toggled_on"
+		} else {
+			""
+		}
+	}
+
+	/// Whether entries have to be loaded before they can be sorted, as opposed to only needing their names.
+	fn needs_metadata(self) -> bool {
+		self.by != SortBy::Name || self.dirs_first
+	}
+
+	fn compare_names(self, a: &str, b: &str, names: &Names) -> Ordering {
+		self.order.apply(names.compare(a, b))
+	}
+
+	fn compare(self, a: &Entry, b: &Entry, names: &Names) -> Ordering {
+		// directories stay first regardless of the sort order
+		let dirs_first = if self.dirs_first {
+			b.is_dir().cmp(&a.is_dir())
+		} else {
+			Ordering::Equal
+		};
+
+		dirs_first.then_with(|| self.order.apply(self.by.compare(a, b, names)))
+	}
 }
 
 pub async fn handler(
 	extract::Path(user_path): extract::Path<PathBuf>,
 	extract::Extension(config): extract::Extension<Arc<Config>>,
+	extract::Extension(dir_sizes): extract::Extension<Arc<DirSizes>>,
 	extract::Query(sorting): extract::Query<Sorting>,
+	extract::Query(page): extract::Query<Page>,
+	extract::Query(download): extract::Query<Download>,
 	request: Request<Body>,
 ) -> Result<Response, ErrorResponse> {
 	super::assert_path_safe(&user_path)?;
 
 	if config.exclude_dotfiles && super::is_hidden_path(&user_path) {
-		return Ok(http::StatusCode::NOT_FOUND.into_response());
+		return Ok(error::NotFound.into_response());
 	}
 
 	let relative_path = user_path.strip_prefix("/").unwrap();
-	let fs_path = config.index_root.join(relative_path);
+	let fs_path = super::resolve_path(&config, relative_path).await?;
 	let metadata = tokio::fs::metadata(&fs_path)
 		.await
 		.map_err(io_ctx("reading metadata"))?;
+	let base = request.extensions().get::<Mount>().unwrap().base.clone();
 
-	if metadata.is_dir() {
-		index_directory(
-			user_path.to_string_lossy().into_owned(),
-			&fs_path,
-			sorting,
-			config.exclude_dotfiles,
-		)
-		.await
-		.map(IntoResponse::into_response)
-	} else {
-		send_file_directly(request, fs_path)
-			.await
-			.map(IntoResponse::into_response)
+	if !metadata.is_dir() {
+		let mut response = file::send(request, &config, fs_path, download.download.is_some()).await;
+		set_cache_control(&mut response, &config.cache_control.files);
+		return Ok(response);
 	}
+
+	let media = request.extensions().get::<Arc<MediaCache>>().unwrap();
+	let loader = Loader {
+		config: &config,
+		dir_sizes: &dir_sizes,
+		media: sorting.by.needs_media().then(|| MediaProbe::new(media)),
+	};
+	let template = index_directory(
+		user_path.to_string_lossy().into_owned(),
+		&fs_path,
+		&base,
+		sorting,
+		page,
+		&loader,
+	)
+	.await?;
+	// the tag covers everything that the listing is rendered from, since much of it, like the sizes of subdirectories, the targets of symlinks, and the readme, can change without changing the mtime of the directory.
+	// there is no `Last-Modified` for the same reason.
+	// it is hashed as it is serialized, since a large listing would take a lot of memory to serialize at once.
+	let mut key = md5::Context::new();
+	key.consume(concat!(env!("CARGO_PKG_VERSION"), "\0"));
+	serde_json::to_writer(&mut key, &template).unwrap();
+	let validators = Validators::from_digest(key.compute(), true, None);
+	let mut response = if validators.is_fresh(request.headers()) {
+		validators.not_modified()
+	} else {
+		let mut response = template.into_response();
+		validators.apply(&mut response);
+		response
+	};
+	set_cache_control(&mut response, &config.cache_control.listings);
+	Ok(response)
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Clone, Copy)]
@@ -184,16 +300,20 @@ enum ThumbnailType {
 	File,
 	Unknown,
 	Rich(RichType),
+	Unreadable(Problem),
 }
 
 impl ThumbnailType {
-	fn url(self, lazy_rich: impl FnOnce() -> String) -> std::borrow::Cow<'static, str> {
-		match self {
-			Self::Directory => "/static/directory.png".into(),
-			Self::File => "/static/file.png".into(),
-			Self::Unknown => "/static/unknown.png".into(),
-			Self::Rich(..) => lazy_rich().into(),
-		}
+	/// Icons are static files under `base_path`.
+	fn url(self, base_path: &str, lazy_rich: impl FnOnce() -> String) -> String {
+		let icon = match self {
+			Self::Directory => "directory.png",
+			Self::File => "file.png",
+			Self::Unknown => "unknown.png",
+			Self::Rich(..) => return lazy_rich(),
+			Self::Unreadable(..) => "unreadable.png",
+		};
+		format!("{base_path}/static/{icon}")
 	}
 
 	fn alt(self) -> &'static str {
@@ -202,12 +322,48 @@ impl ThumbnailType {
 			Self::File => "file",
 			Self::Unknown => "unknown file type",
 			Self::Rich(..) => "rich thumbnail",
+			Self::Unreadable(problem) => problem.describe(),
 		}
 	}
 
 	#[must_use]
-	fn is_rich(self) -> bool {
-		matches!(self, Self::Rich(..))
+	fn has_preview(self) -> bool {
+		matches!(self, Self::Rich(ty) if ty.has_preview())
+	}
+}
+
+/// Why an entry could not be loaded.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Problem {
+	BrokenLink,
+	PermissionDenied,
+	Other,
+}
+
+impl Problem {
+	fn new(error: &std::io::Error, symlink: bool) -> Self {
+		match error.kind() {
+			std::io::ErrorKind::NotFound if symlink => Self::BrokenLink,
+			std::io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+			_ => Self::Other,
+		}
+	}
+
+	fn describe(self) -> &'static str {
+		match self {
+			Self::BrokenLink => "broken link",
+			Self::PermissionDenied => "permission denied",
+			Self::Other => "could not be read",
+		}
+	}
+
+	fn tooltip(self) -> &'static str {
+		match self {
+			Self::BrokenLink => "The target of this link does not exist.",
+			Self::PermissionDenied => "The server does not have permission to read this entry.",
+			Self::Other => "The server could not read this entry.",
+		}
 	}
 }
 
@@ -216,140 +372,330 @@ struct Entry {
 	name: String,
 	#[serde(flatten)]
 	size: Size,
+	/// The total size of a directory, if it has been computed.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	total_bytes: Option<u64>,
 	mtime: i64,
 	thumbnail: ThumbnailType,
-	link: bool,
+	/// The target of the symlink, if the entry is one.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	link: Option<String>,
+	#[serde(flatten)]
+	media: crate::thumbnail::MediaInfo,
 }
 
-async fn get_entries(fs_path: &Path, exclude_dotfiles: bool) -> std::io::Result<Vec<Entry>> {
-	enum Error {
-		Tokio(tokio::task::JoinError),
-		Io(std::io::Error),
+impl Entry {
+	fn is_dir(&self) -> bool {
+		matches!(self.thumbnail, ThumbnailType::Directory)
 	}
 
-	let ret = FuturesUnordered::new();
+	fn problem(&self) -> Option<Problem> {
+		match self.thumbnail {
+			ThumbnailType::Unreadable(problem) => Some(problem),
+			_ => None,
+		}
+	}
+
+	fn extension(&self) -> Option<&str> {
+		if self.is_dir() {
+			return None;
+		}
+		Path::new(&self.name)
+			.extension()
+			.and_then(std::ffi::OsStr::to_str)
+	}
+}
+
+struct RawEntry {
+	name: String,
+	entry: tokio::fs::DirEntry,
+}
+
+async fn read_raw_entries(fs_path: &Path, config: &Config) -> std::io::Result<Vec<RawEntry>> {
+	let mut ret = Vec::new();
 
 	let mut entries = tokio::fs::read_dir(fs_path).await?;
 
 	while let Some(entry) = entries.next_entry().await? {
 		let name = entry.file_name();
-		if exclude_dotfiles && super::starts_with_dot(&name) {
+		if config.exclude_dotfiles && super::starts_with_dot(&name) {
+			continue;
+		}
+		// one unreadable entry should not hide the rest, so it is listed with its problem by `load_entry`
+		let maybe_symlink = match entry.file_type().await {
+			Ok(file_type) => file_type.is_symlink(),
+			Err(error) => {
+				tracing::debug!(path = ?entry.path(), "could not read type of entry: {error}");
+				// so that it is only listed if it would be as a link
+				true
+			}
+		};
+		if maybe_symlink && !link_allowed(&entry, config).await {
 			continue;
 		}
-		ret.push(
-			tokio::spawn(async move {
-				let maybe_symlink_metadata = entry.metadata().await?;
-				let name = name.to_string_lossy().into_owned();
-				let symlink = maybe_symlink_metadata.is_symlink();
-
-				let (path, metadata) = if symlink {
-					let canonical = tokio::fs::canonicalize(entry.path()).await?;
-					let canonical_metadata = tokio::fs::metadata(&canonical).await?;
-					(canonical, canonical_metadata)
-				} else {
-					// not symlink metadata
-					(entry.path(), maybe_symlink_metadata)
-				};
-
-				let (thumbnail, size) = if metadata.is_dir() {
-					let mut dir_entries = tokio::fs::read_dir(&path).await?;
-					let stream = poll_fn(|ctx| dir_entries.poll_next_entry(ctx).map(Result::transpose));
-					let mut count = 0;
-					stream
-						.try_for_each(|_entry| {
-							count += 1;
-							async { Ok(()) }
-						})
-						.await?;
-					(ThumbnailType::Directory, Size::Items(count))
-				} else {
-					let extension = path.extension().and_then(std::ffi::OsStr::to_str);
-					let thumbnail = extension
-						.and_then(crate::thumbnail::Type::from_extension)
-						.map_or_else(
-							|| {
-								if metadata.is_file() {
-									ThumbnailType::File
-								} else {
-									ThumbnailType::Unknown
-								}
-							},
-							ThumbnailType::Rich,
-						);
-
-					(thumbnail, Size::Bytes(metadata.len()))
-				};
-
-				std::io::Result::Ok(Entry {
-					name,
-					size,
-					mtime: metadata.st_mtime(),
-					thumbnail,
-					link: symlink,
+		ret.push(RawEntry {
+			name: name.to_string_lossy().into_owned(),
+			entry,
+		});
+	}
+
+	Ok(ret)
+}
+
+/// Links that cannot be followed are left out of the listing entirely.
+async fn link_allowed(entry: &tokio::fs::DirEntry, config: &Config) -> bool {
+	match config.symlinks {
+		Symlinks::Follow => true,
+		Symlinks::Hide => false,
+		Symlinks::WithinRoot => match tokio::fs::canonicalize(entry.path()).await {
+			Ok(canonical) => config.symlinks.allows(&config.index_root, &canonical, true),
+			// broken, so there is nothing to escape to
+			Err(_) => true,
+		},
+	}
+}
+
+struct Loader<'a> {
+	config: &'a Config,
+	dir_sizes: &'a Arc<DirSizes>,
+	/// Only needed for sorting by media information.
+	media: Option<MediaProbe<'a>>,
+}
+
+impl Loader<'_> {
+	/// Entries that cannot be loaded are still listed, along with the reason.
+	async fn load_entry(&self, raw_entry: RawEntry) -> Entry {
+		let error = match self.try_load_entry(&raw_entry).await {
+			Ok(entry) => return entry,
+			Err(error) => error,
+		};
+
+		let RawEntry { name, entry } = raw_entry;
+		let link_metadata = entry.metadata().await.ok();
+		let symlink = link_metadata
+			.as_ref()
+			.is_some_and(std::fs::Metadata::is_symlink);
+		let problem = Problem::new(&error, symlink);
+		tracing::debug!(path = ?entry.path(), ?problem, "could not load entry: {error}");
+
+		Entry {
+			name,
+			size: Size::Bytes(0),
+			total_bytes: None,
+			mtime: link_metadata.map_or(0, |metadata| metadata.st_mtime()),
+			thumbnail: ThumbnailType::Unreadable(problem),
+			link: None,
+			media: crate::thumbnail::MediaInfo::default(),
+		}
+	}
+
+	async fn try_load_entry(&self, RawEntry { name, entry }: &RawEntry) -> std::io::Result<Entry> {
+		let maybe_symlink_metadata = entry.metadata().await?;
+		let symlink = maybe_symlink_metadata.is_symlink();
+
+		let mut link = None;
+		let (path, metadata) = if symlink {
+			let canonical = tokio::fs::canonicalize(entry.path()).await?;
+			let canonical_metadata = tokio::fs::metadata(&canonical).await?;
+			link = Some(self.link_target(entry, &canonical).await?);
+			(canonical, canonical_metadata)
+		} else {
+			// not symlink metadata
+			(entry.path(), maybe_symlink_metadata)
+		};
+
+		let mut total_bytes = None;
+		let (thumbnail, size) = if metadata.is_dir() {
+			let exclude_dotfiles = self.config.exclude_dotfiles;
+			let count = self
+				.dir_sizes
+				.items(&path, metadata.modified()?, exclude_dotfiles)
+				.await?;
+			if self.config.directory_sizes {
+				total_bytes = self.dir_sizes.total_bytes(
+					&path,
+					exclude_dotfiles,
+					Duration::from_secs(self.config.directory_sizes_max_age),
+				);
+			}
+			(ThumbnailType::Directory, Size::Items(count))
+		} else {
+			let extension = path.extension().and_then(std::ffi::OsStr::to_str);
+			let thumbnail = extension
+				.and_then(|extension| {
+					crate::thumbnail::Type::for_extension(extension, &self.config.thumbnailers)
 				})
-			})
-			.map(|join_result| {
-				join_result
-					.map_err(Error::Tokio)
-					.and_then(|io_result| io_result.map_err(Error::Io))
-			}),
-		);
+				.map_or_else(
+					|| {
+						if metadata.is_file() {
+							ThumbnailType::File
+						} else {
+							ThumbnailType::Unknown
+						}
+					},
+					ThumbnailType::Rich,
+				);
+
+			(thumbnail, Size::Bytes(metadata.len()))
+		};
+
+		let media = match (thumbnail, &self.media) {
+			(ThumbnailType::Rich(ty), Some(media)) => media.get(&path, metadata.modified()?, ty).await,
+			_ => crate::thumbnail::MediaInfo::default(),
+		};
+
+		Ok(Entry {
+			name: name.clone(),
+			size,
+			total_bytes,
+			mtime: metadata.st_mtime(),
+			thumbnail,
+			link,
+			media,
+		})
+	}
+
+	/// Relative to `index_root` if the link points within it, so as not to reveal where it is.
+	async fn link_target(
+		&self,
+		entry: &tokio::fs::DirEntry,
+		canonical: &Path,
+	) -> std::io::Result<String> {
+		Ok(match canonical.strip_prefix(&self.config.index_root) {
+			Ok(within_root) => join_paths([&*within_root.to_string_lossy()]),
+			Err(_) => tokio::fs::read_link(entry.path())
+				.await?
+				.to_string_lossy()
+				.into_owned(),
+		})
+	}
+
+	/// Loads the entries, preserving their order, with at most `listing_concurrency` entries being loaded at once.
+	async fn load_entries(&self, raw_entries: impl IntoIterator<Item = RawEntry>) -> Vec<Entry> {
+		futures::stream::iter(raw_entries)
+			.map(|raw_entry| self.load_entry(raw_entry))
+			.buffered(self.config.listing_concurrency.max(1))
+			.collect()
+			.await
+	}
+}
+
+/// For files, `?download` asks for them to be saved rather than displayed.
+#[derive(Deserialize)]
+pub struct Download {
+	download: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy)]
+pub struct Page {
+	#[serde(default)]
+	offset: usize,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	limit: Option<usize>,
+}
+
+impl Page {
+	/// The maximum number of entries on a page. Zero means no limit.
+	/// Clients can only ask for smaller pages than `page_size`, and never for unlimited ones, since pagination is what keeps huge directories cheap.
+	fn limit(self, page_size: usize) -> usize {
+		match (self.limit, page_size) {
+			(None, _) => page_size,
+			(Some(limit), 0) => limit.max(1),
+			(Some(limit), page_size) => limit.clamp(1, page_size),
+		}
 	}
 
-	match ret.try_collect().await {
-		Ok(entries) => Ok(entries),
-		Err(Error::Tokio(error)) => std::panic::resume_unwind(error.into_panic()), /* assume that the task was not cancelled. */
-		Err(Error::Io(error)) => Err(error),
+	/// The range of entries on this page, out of `total` entries.
+	fn range(self, limit: usize, total: usize) -> Range<usize> {
+		let start = self.offset.min(total);
+		let end = match limit {
+			0 => total,
+			limit => start.saturating_add(limit).min(total),
+		};
+		start..end
 	}
 }
 
 async fn index_directory(
 	user_path: String,
 	fs_path: &Path,
+	base: &str,
 	sorting: Sorting,
-	exclude_dotfiles: bool,
-) -> Result<Response, ErrorResponse> {
-	let mut entries = get_entries(fs_path, exclude_dotfiles)
+	page: Page,
+	loader: &Loader<'_>,
+) -> Result<template::Template, ErrorResponse> {
+	let config = loader.config;
+	let mut raw_entries = read_raw_entries(fs_path, config)
 		.await
 		.map_err(io_ctx("reading directory"))?;
-
-	entries.sort_by(move |a, b| {
-		let ordering = sorting.by.compare(a, b);
-		match sorting.order {
-			SortOrder::Ascending => ordering,
-			SortOrder::Descending => ordering.reverse(),
+	let total = raw_entries.len();
+	let limit = page.limit(config.page_size);
+	// so that links to other pages have the limit that was used
+	let page = Page {
+		limit: page.limit.map(|_| limit),
+		..page
+	};
+	let range = page.range(limit, total);
+
+	let entries = if sorting.needs_metadata() {
+		let mut entries = loader.load_entries(raw_entries).await;
+		let names = Names::new(config);
+		entries.sort_by(|a, b| sorting.compare(a, b, &names));
+		entries.truncate(range.end);
+		entries.drain(..range.start);
+		entries
+	} else {
+		// only the entries on this page need to be loaded
+		{
+			// the collator is not `Send`, so it must not be held across an await point
+			let names = Names::new(config);
+			raw_entries.sort_by(|a, b| sorting.compare_names(&a.name, &b.name, &names));
 		}
-	});
-
-	Ok(
-		(
-			[(http::header::CONTENT_TYPE, "text/html")],
-			template::Template {
-				title: &user_path,
-				entries: &entries,
-				sorting,
-			},
-		)
-			.into_response(),
-	)
+		loader.load_entries(raw_entries.drain(range.clone())).await
+	};
+
+	let url = readme::dir_url(base, &user_path);
+	let header = readme::render(fs_path, &url, &config.header_names, config).await;
+	let readme = readme::render(fs_path, &url, &config.readme_names, config).await;
+
+	let writable = match tokio::fs::canonicalize(fs_path).await {
+		Ok(canonical) => config.writable(&canonical).await,
+		Err(_) => None,
+	};
+	let manageable = writable.is_some_and(|writable| writable.manage);
+
+	Ok(template::Template {
+		writable: writable.is_some(),
+		manageable,
+		can_delete: manageable && config.trash_dir.is_some(),
+		title: user_path,
+		base: base.to_owned(),
+		base_path: config.base_path.clone(),
+		header,
+		readme,
+		entries,
+		sorting,
+		page,
+		limit,
+		range,
+		total,
+	})
 }
 
-async fn send_file_directly(
-	request: Request<Body>,
-	fs_path: PathBuf,
-) -> Result<impl IntoResponse, ErrorResponse> {
-	tower_http::services::ServeFile::new(fs_path)
-		.call(request)
-		.await
-		.map_err(|error| {
-			error::Io {
-				context: "serving file directly",
-				error,
-			}
-			.into()
-		})
+/// Uploads are multipart forms, and changes to entries are plain forms.
+async fn post(request: Request<Body>) -> Response {
+	let is_multipart = request
+		.headers()
+		.get(http::header::CONTENT_TYPE)
+		.and_then(|content_type| content_type.to_str().ok())
+		.is_some_and(|content_type| content_type.starts_with("multipart/form-data"));
+	if is_multipart {
+		upload::post.call(request).await
+	} else {
+		manage::post.call(request).await
+	}
 }
 
 pub fn configure() -> Router {
-	Router::new().route("/*path", get(handler))
+	Router::new().route("/*path", get(handler).put(upload::put).post(post))
 }
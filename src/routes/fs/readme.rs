@@ -0,0 +1,75 @@
+use std::path::Path;
+
+use crate::config::Config;
+use crate::util::encode_path;
+
+/// Files larger than this are not rendered.
+const MAX_SIZE: u64 = 256 * 1024;
+
+/// The URL of the listing of `user_path`, as in URLs, which relative links are resolved against.
+/// It is percent-encoded, since the links are appended to it as they are.
+pub(super) fn dir_url(base: &str, user_path: &str) -> String {
+	format!("{base}/fs{}", encode_path(Path::new(user_path)))
+}
+
+/// Find the first of `names` that exists in `dir` and render it as sanitized HTML.
+///
+/// Markdown and HTML files are rendered as HTML, and anything else as preformatted text.
+/// Relative links are resolved against `dir_url`, the URL of the directory listing.
+pub(super) async fn render(
+	dir: &Path,
+	dir_url: &str,
+	names: &[String],
+	config: &Config,
+) -> Option<String> {
+	for name in names {
+		let path = dir.join(name);
+		match read(&path, config).await {
+			Ok(Some(contents)) => return Some(render_contents(&path, dir_url, &contents)),
+			Ok(None) => {}
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+			Err(error) => tracing::warn!(?path, "could not read readme: {error}"),
+		}
+	}
+
+	None
+}
+
+async fn read(path: &Path, config: &Config) -> std::io::Result<Option<String>> {
+	if tokio::fs::symlink_metadata(path).await?.is_symlink() {
+		let canonical = tokio::fs::canonicalize(path).await?;
+		if !config.symlinks.allows(&config.index_root, &canonical, true) {
+			return Ok(None);
+		}
+	}
+
+	let metadata = tokio::fs::metadata(path).await?;
+	if !metadata.is_file() {
+		return Ok(None);
+	}
+	if metadata.len() > MAX_SIZE {
+		tracing::debug!(?path, "readme is too large to render");
+		return Ok(None);
+	}
+
+	let contents = tokio::fs::read(path).await?;
+	Ok(Some(String::from_utf8_lossy(&contents).into_owned()))
+}
+
+fn render_contents(path: &Path, dir_url: &str, contents: &str) -> String {
+	match crate::markup::lowercase_extension(path).as_deref() {
+		Some("md" | "markdown") => crate::markup::markdown(contents, dir_url),
+		Some("html" | "htm") => crate::markup::sanitize(contents, dir_url),
+		_ => crate::markup::plain_text(contents),
+	}
+}
+
+#[test]
+fn test_relative_links() {
+	let url = dir_url("/files", "/notes #1/drafts?");
+	let html = render_contents(Path::new("README.md"), &url, "[image](image.png)");
+	assert!(
+		html.contains(r#"href="/files/fs/notes%20%231/drafts%3F/image.png""#),
+		"{html}"
+	);
+}
@@ -1,14 +1,47 @@
+use std::convert::Infallible;
 use std::fmt::Write as _;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
 
+use axum::body::StreamBody;
 use axum::response::{IntoResponse, Response};
+use futures::Stream;
+use serde::Serialize;
 
-use super::{Entry, SortBy, Sorting};
-use crate::util::join_paths;
+use super::{Entry, Page, RichType, SortBy, Sorting, ThumbnailType};
+use crate::util::encode_path;
 
-pub(super) struct Template<'a> {
-	pub(super) title: &'a str,
-	pub(super) entries: &'a [Entry],
+/// The number of rows rendered into each chunk of the response body.
+const ROWS_PER_CHUNK: usize = 256;
+
+/// Serialized to derive the entity tag of the listing, so everything that is rendered must come from here.
+#[derive(Serialize)]
+pub(super) struct Template {
+	pub(super) title: String,
+	/// Prepended to the URLs of listings, thumbnails, and previews.
+	pub(super) base: String,
+	/// The configured `base_path`, which static files are under.
+	pub(super) base_path: String,
+	/// Sanitized HTML rendered above the entries.
+	pub(super) header: Option<String>,
+	/// Sanitized HTML rendered below the entries.
+	pub(super) readme: Option<String>,
+	/// The entries on the current page.
+	pub(super) entries: Vec<Entry>,
 	pub(super) sorting: Sorting,
+	pub(super) page: Page,
+	/// The maximum number of entries on a page, or zero if there is no limit.
+	pub(super) limit: usize,
+	/// The range of all entries that is on the current page.
+	pub(super) range: Range<usize>,
+	pub(super) total: usize,
+	/// Whether files can be uploaded to the directory.
+	pub(super) writable: bool,
+	/// Whether entries can be created, renamed, and moved in the directory.
+	pub(super) manageable: bool,
+	/// Whether entries can be moved to the trash.
+	pub(super) can_delete: bool,
 }
 
 macro_rules! if_attr {
@@ -21,39 +54,297 @@ macro_rules! if_attr {
 	};
 }
 
-impl Template<'_> {
-	fn render(&self) -> String {
-		let url = join_paths(["/fs", self.title]);
+/// Everything up to and including the opening `<body>` tag, shared with the error pages.
+/// `up` is the URL of the listing above the page, if there is one.
+pub fn write_document_start(out: &mut String, base_path: &str, title: &str, up: Option<&str>) {
+	write!(
+		out,
+		"<!DOCTYPE html>\
+		<html lang=\"en\">\
+			<head>\
+				<meta charset=\"UTF-8\" />\
+				<meta http-equiv=\"X-UA-Compatible\" content=\"IE=edge\" />\
+				<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\" />\
+				<title>{}</title>\
+				<link rel=\"stylesheet\" type=\"text/css\" href=\"{base_path}/static/index.css\">\
+				<link rel=\"stylesheet\" type=\"text/css\" href=\"{base_path}/static/highlight.css\">\
+				{up}\
+			</head>\
+			<body>",
+		html_escape::encode_text(title),
+		base_path = html_escape::encode_double_quoted_attribute(base_path),
+		up = up.map_or_else(String::new, |up| format!(
+			"<link rel=\"up\" href=\"{}\">",
+			html_escape::encode_double_quoted_attribute(up)
+		)),
+	)
+	.unwrap();
+}
 
-		let mut ret = String::new();
+impl Template {
+	fn render_sort_options(&self, out: &mut String) {
+		write!(out, "<nav id=\"sortOptions\">Sort by:").unwrap();
+		for (column, label) in [
+			(SortBy::Type, "Type"),
+			(SortBy::Captured, "Capture date"),
+			(SortBy::Dimensions, "Dimensions"),
+			(SortBy::Duration, "Duration"),
+		] {
+			let (link, class) = self.sorting.both_for(column);
+			write!(out, " <a class=\"{class}\" href=\"{link}\">{label}</a>").unwrap();
+		}
 		write!(
-			ret,
-			"<!DOCTYPE html>\
-			<html lang=\"en\">\
-				<head>\
-					<meta charset=\"UTF-8\" />\
-					<meta http-equiv=\"X-UA-Compatible\" content=\"IE=edge\" />\
-					<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\" />\
-					<title>{}</title>\
-					<link rel=\"stylesheet\" type=\"text/css\" href=\"/static/index.css\">\
-				</head>\
-				<body>",
-			html_escape::encode_text(&self.title),
+			out,
+			" | <a class=\"{}\" href=\"{}\">Directories first</a></nav>",
+			self.sorting.dirs_first_class(),
+			self.sorting.dirs_first_link(),
 		)
 		.unwrap();
+	}
+
+	/// The sorting and page size of this listing, which are kept when going to other listings.
+	/// Empty if they are the defaults, so that links stay short.
+	fn navigation_query(&self) -> String {
+		if self.sorting == Sorting::default() && self.page.limit.is_none() {
+			return String::new();
+		}
+		let mut query = self.sorting.link().to_string();
+		if let Some(limit) = self.page.limit {
+			write!(query, "&limit={limit}").unwrap();
+		}
+		query
+	}
+
+	/// The URL of the listing of `user_path`, keeping the sorting and page size.
+	fn listing_url(&self, user_path: &Path) -> String {
+		format!(
+			"{}/fs{}{}",
+			self.base,
+			encode_path(user_path),
+			self.navigation_query()
+		)
+	}
 
-		if self.title != "/" {
+	fn render_breadcrumb(&self, out: &mut String) {
+		let current = Path::new(&self.title);
+		let mut crumbs = vec![(Path::new("/"), "Root")];
+		crumbs.extend(current.ancestors().filter_map(|ancestor| {
+			let name = ancestor.file_name()?.to_str()?;
+			Some((ancestor, name))
+		}));
+		crumbs[1..].reverse();
+
+		write!(out, "<nav id=\"breadcrumb\" aria-label=\"Breadcrumb\">").unwrap();
+		for (idx, (path, name)) in crumbs.iter().enumerate() {
+			if idx > 0 {
+				out.push_str(" / ");
+			}
+			let is_current = idx == crumbs.len() - 1;
+			write!(
+				out,
+				"<a href=\"{}\"{}>{}</a>",
+				html_escape::encode_double_quoted_attribute(&self.listing_url(path)),
+				if is_current {
+					" aria-current=\"page\""
+				} else {
+					""
+				},
+				html_escape::encode_text(name),
+			)
+			.unwrap();
+		}
+		out.push_str("</nav>");
+	}
+
+	/// The listing of the parent directory, unless this is the root.
+	fn up_url(&self) -> Option<String> {
+		Path::new(&self.title)
+			.parent()
+			.map(|parent| self.listing_url(parent))
+	}
+
+	/// The URL of the entry named `name` in this listing under `route`, such as `/fs` or `/thumb`.
+	fn entry_url(&self, route: &str, name: &str) -> String {
+		format!(
+			"{}{route}{}",
+			self.base,
+			encode_path(&Path::new(&self.title).join(name))
+		)
+	}
+
+	fn render_entry(&self, out: &mut String, idx: usize, entry: &Entry) {
+		let url = self.entry_url("/fs", &entry.name);
+		// files are served as is, so only directories get the query
+		let href = if entry.is_dir() {
+			format!("{url}{}", self.navigation_query())
+		} else {
+			url.clone()
+		};
+		let href = html_escape::encode_double_quoted_attribute(&href);
+
+		let data = serde_json::to_string(entry).unwrap();
+		let data = html_escape::encode_double_quoted_attribute(&data);
+		let url = html_escape::encode_double_quoted_attribute(&url);
+		let preview_url = match entry.thumbnail {
+			ThumbnailType::Rich(RichType::Text) => {
+				let preview_url = self.entry_url("/preview", &entry.name);
+				format!(
+					"data-entry-preview-url=\"{}\"",
+					html_escape::encode_double_quoted_attribute(&preview_url)
+				)
+			}
+			_ => String::new(),
+		};
+		let thumbnail_url = entry
+			.thumbnail
+			.url(&self.base_path, || self.entry_url("/thumb", &entry.name));
+		let thumbnail_alt = entry.thumbnail.alt();
+		let maybe_link = if_attr!(entry.link.is_some() => class="icon-link");
+		let if_rich = if_attr!(entry.thumbnail.has_preview() => class="has-preview");
+		let name = html_escape::encode_text(&entry.name);
+		let link_target = entry.link.as_ref().map_or_else(String::new, |target| {
+			format!(
+				" <span class=\"link-target\" title=\"Link target\">&rarr; {}</span>",
+				html_escape::encode_text(target)
+			)
+		});
+		let maybe_link_warning = if_attr!(entry.link.is_some() => title="This applies to the file or directory that the link points to, not the link itself.");
+		let (size, problem_tooltip) = if let Some(problem) = entry.problem() {
+			(
+				problem.describe().to_owned(),
+				format!("title=\"{}\"", problem.tooltip()),
+			)
+		} else {
+			let total_size = entry
+				.total_bytes
+				.map(|total| format!(" ({})", super::Size::Bytes(total)))
+				.unwrap_or_default();
+			(format!("{}{total_size}", entry.size), String::new())
+		};
+		let actions = if self.manageable {
+			let delete = if self.can_delete {
+				"<button type=\"button\" data-action=\"delete\">Delete</button>"
+			} else {
+				""
+			};
+			format!(
+				"<td class=\"entry-actions\">\
+					<button type=\"button\" data-action=\"rename\">Rename</button>\
+					<button type=\"button\" data-action=\"move\">Move</button>\
+					{delete}\
+				</td>"
+			)
+		} else {
+			String::new()
+		};
+		let time = time::OffsetDateTime::from_unix_timestamp(entry.mtime).unwrap().format(time::macros::format_description!("[year]-[month]-[day] [hour padding:zero repr:24]:[minute padding:zero]:[second padding:zero]Z")).unwrap();
+
+		write!(
+			out,
+			"<tr data-entry=\"{data}\" data-entry-url=\"{url}\" {preview_url} data-entry-idx=\"{idx}\">\
+				<link rel=\"prefetch\" href=\"{href}\">\
+				<td class=\"entry-thumbnail\"><img src=\"{thumbnail_url}\" alt=\"{thumbnail_alt}\" {maybe_link} {problem_tooltip}></td>\
+				<td class=\"entry-name\"><a href=\"{href}\" {if_rich}>{name}</a>{link_target}</td>\
+				<td class=\"entry-size\" {maybe_link_warning} {problem_tooltip}>{size}</td>\
+				<td class=\"entry-mtime\">{time}</td>\
+				{actions}\
+			</tr>",
+		)
+		.unwrap();
+	}
+
+	fn render_pages(&self, out: &mut String) {
+		let limit = self.limit;
+		if limit == 0 || (self.range.start == 0 && self.range.end == self.total) {
+			return;
+		}
+
+		let link = |offset: usize| {
+			let page = Page {
+				offset,
+				..self.page
+			};
+			let link = format!(
+				"{}&{}",
+				self.sorting.link(),
+				serde_urlencoded::to_string(page).unwrap()
+			);
+			html_escape::encode_double_quoted_attribute(&link).into_owned()
+		};
+		let last_offset = self.total.saturating_sub(1) / limit * limit;
+		// the offset can be past the end, such as after entries were deleted
+		let (summary, previous) = if self.range.is_empty() {
+			(
+				format!("No entries on this page, out of {}", self.total),
+				last_offset,
+			)
+		} else {
+			(
+				format!(
+					"Entries {}&ndash;{} of {}",
+					self.range.start + 1,
+					self.range.end,
+					self.total
+				),
+				self.range.start.saturating_sub(limit),
+			)
+		};
+
+		write!(
+			out,
+			"<nav class=\"pages\">\
+				<a href=\"{first}\" rel=\"first\">First</a> \
+				<a href=\"{previous}\" rel=\"prev\">Previous</a> \
+				<span>{summary}</span> \
+				<a href=\"{next}\" rel=\"next\">Next</a> \
+				<a href=\"{last}\" rel=\"last\">Last</a>\
+			</nav>",
+			first = link(0),
+			previous = link(previous),
+			next = link(if self.range.end < self.total {
+				self.range.end
+			} else {
+				self.range.start
+			}),
+			last = link(last_offset),
+		)
+		.unwrap();
+	}
+
+	fn render_head(&self) -> String {
+		let mut ret = String::new();
+		let up = self.up_url();
+		write_document_start(&mut ret, &self.base_path, &self.title, up.as_deref());
+
+		self.render_breadcrumb(&mut ret);
+		if let Some(up) = &up {
 			write!(
 				ret,
-				r#"<a href="{}">Go up</a>"#,
-				html_escape::encode_double_quoted_attribute(&self.title)
+				r#" <a href="{}" rel="up">Go up</a>"#,
+				html_escape::encode_double_quoted_attribute(up)
 			)
 			.unwrap();
 		}
 
-		let (class_for_name, link_for_name) = self.sorting.both_for(SortBy::Name);
-		let (class_for_size, link_for_size) = self.sorting.both_for(SortBy::Size);
-		let (class_for_mtime, link_for_mtime) = self.sorting.both_for(SortBy::MTime);
+		if let Some(header) = &self.header {
+			write!(
+				ret,
+				"<section class=\"readme\" id=\"header\">{header}</section>"
+			)
+			.unwrap();
+		}
+
+		self.render_sort_options(&mut ret);
+		self.render_pages(&mut ret);
+
+		let (link_for_name, class_for_name) = self.sorting.both_for(SortBy::Name);
+		let (link_for_size, class_for_size) = self.sorting.both_for(SortBy::Size);
+		let (link_for_mtime, class_for_mtime) = self.sorting.both_for(SortBy::MTime);
+		let actions = if self.manageable {
+			"<th class=\"entry-actions\"></th>"
+		} else {
+			""
+		};
 		write!(
 			ret,
 			"<table id=\"entries\">\
@@ -63,79 +354,116 @@ impl Template<'_> {
 					<th class=\"entry-name {class_for_name}\"><a href=\"{link_for_name}\">Name</a></th>\
 					<th class=\"entry-size {class_for_size}\"><a href=\"{link_for_size}\">Size</a></th>\
 					<th class=\"entry-mtime {class_for_mtime}\"><a href=\"{link_for_mtime}\">MTime</a></th>\
+					{actions}\
 				</tr>\
 			</thead>\
 			<tbody id=\"entriesList\">"
 		)
 		.unwrap();
 
-		for (idx, entry) in self.entries.iter().enumerate() {
-			let url = join_paths([url.as_str(), &entry.name]);
-
-			let data = serde_json::to_string(entry).unwrap();
-			let data = html_escape::encode_double_quoted_attribute(&data);
-			let url = html_escape::encode_double_quoted_attribute(&url);
-			let thumbnail_url = entry
-				.thumbnail
-				.url(|| join_paths(["/thumb", self.title, &entry.name]));
-			let thumbnail_alt = entry.thumbnail.alt();
-			let maybe_link = if_attr!(entry.link => class="icon-link");
-			let if_rich = if_attr!(entry.thumbnail.is_rich() => class="has-preview", onclick="preview_on_click(this, event)");
-			let name = html_escape::encode_text(&entry.name);
-			let maybe_link_warning = if_attr!(entry.link => title="This applies to the file or directory that the link points to, not the link itself.");
-			let size = entry.size;
-			let time = time::OffsetDateTime::from_unix_timestamp(entry.mtime).unwrap().format(time::macros::format_description!("[year]-[month]-[day] [hour padding:zero repr:24]:[minute padding:zero]:[second padding:zero]Z")).unwrap();
+		ret
+	}
 
-			write!(
-				ret,
-				"<tr data-entry=\"{data}\" data-entry-url=\"{url}\" data-entry-idx=\"{idx}\">\
-					<link rel=\"prefetch\" href=\"{url}\">\
-					<td class=\"entry-thumbnail\"><img src=\"{thumbnail_url}\" alt=\"{thumbnail_alt}\" {maybe_link}></td>\
-					<td class=\"entry-name\"><a href=\"{url}\" {if_rich}>{name}</a></td>\
-					<td class=\"entry-size\" {maybe_link_warning}>{size}</td>\
-					<td class=\"entry-mtime\">{time}</td>\
-				</tr>",
-			)
-			.unwrap();
+	fn render_rows(&self, range: Range<usize>) -> String {
+		let mut ret = String::new();
+		for (idx, entry) in self.entries[range.clone()].iter().enumerate() {
+			self.render_entry(&mut ret, range.start + idx, entry);
 		}
+		ret
+	}
+
+	fn render_foot(&self) -> String {
+		let mut ret = String::new();
 
 		let no_entries = if self.entries.is_empty() {
 			"<p>(No Entries)</p>"
 		} else {
 			""
 		};
+		write!(ret, "</tbody></table>{no_entries}").unwrap();
+
+		self.render_pages(&mut ret);
+
+		if self.writable {
+			write!(
+				ret,
+				"<form id=\"uploadForm\" method=\"post\" enctype=\"multipart/form-data\">\
+					<label>Upload files (or drop them onto the page): <input type=\"file\" name=\"file\" multiple required></label>\
+					<button type=\"submit\">Upload</button>\
+					<output id=\"uploadStatus\"></output>\
+				</form>"
+			)
+			.unwrap();
+		}
+
+		if self.manageable {
+			write!(
+				ret,
+				"<p id=\"manage\"><button type=\"button\" id=\"manageMkdir\">New folder</button></p>"
+			)
+			.unwrap();
+		}
+
+		if let Some(readme) = &self.readme {
+			write!(
+				ret,
+				"<section class=\"readme\" id=\"readme\">{readme}</section>"
+			)
+			.unwrap();
+		}
+
 		write!(ret, "\
-			</tbody>\
-		</table>\
-		{no_entries}\
 		<figure id=\"preview\">\
 			<div id=\"previewItemContainer\"></div>\
 			<figcaption id=\"previewBar\">\
-				<button id=\"previewPrevious\" title=\"Previous\" onclick=\"preview_previous()\"><img src=\"/static/previous.png\" alt=\"Previous\"></button>\
+				<button id=\"previewPrevious\" title=\"Previous\"><img src=\"{base_path}/static/previous.png\" alt=\"Previous\"></button>\
 				<span id=\"previewPosition\">\
 					<span id=\"previewPositionCurrent\" title=\"Current Index\"></span>\
 					&sol;\
 					<span id=\"previewPositionTotal\" title=\"Number of Items\"></span>\
 				</span>\
-				<button id=\"previewNext\" title=\"Next (Double-click for slideshow)\" onclick=\"preview_next()\" ondblclick=\"slideshow_start()\"><img src=\"/static/next.png\" alt=\"Next\"></button>\
-				<button id=\"previewFullscreenToggle\" onclick=\"fullscreen_toggle()\" title=\"Toggle fullscreen\"><img id=\"previewFullscreenButton\" src=\"/static/fullscreen.png\" alt=\"Enter fullscreen\"></button>\
-				<button id=\"previewSlideshowToggle\" onclick=\"slideshow_toggle()\" title=\"Toggle slideshow\"><img id=\"previewSlideshowButton\" src=\"/static/slideshow-start.png\" alt=\"Start slideshow\"></button>\
-				<button id=\"previewDownload\" title=\"Download\" onclick=\"preview_download()\"><img src=\"/static/download.png\" alt=\"Download\"></button>\
-				<button id=\"previewClose\" title=\"Close\" onclick=\"preview_close()\"><img src=\"/static/close.png\" alt=\"Close\"></button>\
+				<button id=\"previewNext\" title=\"Next (Double-click for slideshow)\"><img src=\"{base_path}/static/next.png\" alt=\"Next\"></button>\
+				<button id=\"previewFullscreenToggle\" title=\"Toggle fullscreen\"><img id=\"previewFullscreenButton\" src=\"{base_path}/static/fullscreen.png\" alt=\"Enter fullscreen\"></button>\
+				<button id=\"previewSlideshowToggle\" title=\"Toggle slideshow\"><img id=\"previewSlideshowButton\" src=\"{base_path}/static/slideshow-start.png\" alt=\"Start slideshow\"></button>\
+				<button id=\"previewDownload\" title=\"Download\"><img src=\"{base_path}/static/download.png\" alt=\"Download\"></button>\
+				<button id=\"previewClose\" title=\"Close\"><img src=\"{base_path}/static/close.png\" alt=\"Close\"></button>\
 			</figcaption>\
 		</figure>\
 		\
 		<link rel=\"preload\" id=\"prefetchBefore\">\
 		<link rel=\"preload\" id=\"prefetchAfter\">\
 		\
-		<script type=\"text/javascript\" src=\"/static/index.js\"></script></body></html>").unwrap();
+		<script type=\"text/javascript\" src=\"{base_path}/static/index.js\"></script></body></html>",
+			base_path = html_escape::encode_double_quoted_attribute(&self.base_path),
+		).unwrap();
 
 		ret
 	}
+
+	fn into_stream(self) -> impl Stream<Item = Result<String, Infallible>> + Send {
+		let this = Arc::new(self);
+		let len = this.entries.len();
+
+		let head = std::iter::once_with({
+			let this = Arc::clone(&this);
+			move || this.render_head()
+		});
+		let rows = (0..len).step_by(ROWS_PER_CHUNK).map({
+			let this = Arc::clone(&this);
+			move |start| this.render_rows(start..(start + ROWS_PER_CHUNK).min(len))
+		});
+		let foot = std::iter::once_with(move || this.render_foot());
+
+		futures::stream::iter(head.chain(rows).chain(foot).map(Ok))
+	}
 }
 
-impl IntoResponse for Template<'_> {
+impl IntoResponse for Template {
 	fn into_response(self) -> Response {
-		([("Content-Type", "text/html")], self.render()).into_response()
+		(
+			[("Content-Type", "text/html")],
+			StreamBody::new(self.into_stream()),
+		)
+			.into_response()
 	}
 }
@@ -0,0 +1,317 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use axum::extract;
+use axum::response::{ErrorResponse, IntoResponse, Redirect, Response};
+use futures::{Stream, StreamExt as _};
+use http::header::CONTENT_LENGTH;
+use http::{HeaderMap, StatusCode};
+use hyper::body::Bytes;
+use nix::errno::Errno;
+use nix::fcntl::{renameat2, RenameFlags};
+use tokio::io::AsyncWriteExt as _;
+
+use super::audit::{self, Client, Record};
+use crate::config::{Config, Writable};
+use crate::error::{self, io_ctx};
+
+#[derive(Debug)]
+enum UploadError {
+	TooLarge,
+	Exists,
+	Body(String),
+	Io(error::Io<std::io::Error>),
+}
+
+impl IntoResponse for UploadError {
+	fn into_response(self) -> Response {
+		match self {
+			Self::TooLarge => error::response(StatusCode::PAYLOAD_TOO_LARGE, "upload is too large"),
+			Self::Exists => error::response(StatusCode::CONFLICT, "file already exists"),
+			Self::Body(message) => error::BadRequest(message).into_response(),
+			Self::Io(error) => error.into_response(),
+		}
+	}
+}
+
+impl From<error::Io<std::io::Error>> for UploadError {
+	fn from(error: error::Io<std::io::Error>) -> Self {
+		Self::Io(error)
+	}
+}
+
+/// `PUT /fs/<dir>/<name>` with the contents as the body, replacing the file if it exists.
+pub(super) async fn put(
+	extract::Path(user_path): extract::Path<PathBuf>,
+	extract::Extension(config): extract::Extension<Arc<Config>>,
+	client: Client,
+	headers: HeaderMap,
+	body: extract::BodyStream,
+) -> Result<Response, ErrorResponse> {
+	crate::routes::assert_path_safe(&user_path)?;
+	let (Some(dir_path), Some(name)) = (
+		user_path.parent(),
+		user_path.file_name().and_then(std::ffi::OsStr::to_str),
+	) else {
+		return Err(error::BadRequest("invalid file name").into());
+	};
+	check_name(&config, name)?;
+	let (dir, writable) = target_dir(&config, dir_path).await?;
+	if content_length(&headers).is_some_and(|length| length > writable.max_upload_bytes) {
+		return Err(UploadError::TooLarge.into());
+	}
+
+	let mut budget = writable.max_upload_bytes;
+	let replaced = write(&dir, name, body, &mut budget, true).await?;
+	audit::record(
+		&config,
+		Record {
+			action: "upload",
+			path: &user_path,
+			destination: None,
+			client: &client,
+		},
+	)
+	.await;
+	Ok(
+		if replaced {
+			StatusCode::NO_CONTENT
+		} else {
+			StatusCode::CREATED
+		}
+		.into_response(),
+	)
+}
+
+/// `POST /fs/<dir>` with a `multipart/form-data` body, like the upload form in listings.
+/// Every part with a file name is saved in the directory, which must not already contain files with those names.
+pub(super) async fn post(
+	extract::Path(user_path): extract::Path<PathBuf>,
+	extract::Extension(config): extract::Extension<Arc<Config>>,
+	client: Client,
+	headers: HeaderMap,
+	mut multipart: extract::Multipart,
+) -> Result<Response, ErrorResponse> {
+	crate::routes::assert_path_safe(&user_path)?;
+	let (dir, writable) = target_dir(&config, &user_path).await?;
+	if content_length(&headers).is_some_and(|length| length > writable.max_upload_bytes) {
+		return Err(UploadError::TooLarge.into());
+	}
+
+	let mut budget = writable.max_upload_bytes;
+	while let Some(field) = multipart
+		.next_field()
+		.await
+		.map_err(|error| UploadError::Body(error.to_string()))?
+	{
+		// browsers send an empty file name if no file was chosen
+		let Some(name) = field
+			.file_name()
+			.filter(|name| !name.is_empty())
+			.map(str::to_owned)
+		else {
+			continue;
+		};
+		check_name(&config, &name)?;
+		write(&dir, &name, field, &mut budget, false).await?;
+		audit::record(
+			&config,
+			Record {
+				action: "upload",
+				path: &user_path.join(&name),
+				destination: None,
+				client: &client,
+			},
+		)
+		.await;
+	}
+
+	Ok(redirect_to(&config, &user_path))
+}
+
+/// To the listing of a directory, as in URLs.
+pub(super) fn redirect_to(config: &Config, dir_path: &Path) -> Response {
+	Redirect::to(&format!(
+		"{}/fs{}",
+		config.base_path,
+		crate::util::encode_path(dir_path)
+	))
+	.into_response()
+}
+
+/// The canonical directory that an upload into `dir_path` would be written to, if uploads are allowed there.
+pub(super) async fn target_dir<'a>(
+	config: &'a Config,
+	dir_path: &Path,
+) -> Result<(PathBuf, &'a Writable), ErrorResponse> {
+	if config.exclude_dotfiles && crate::routes::is_hidden_path(dir_path) {
+		return Err(error::NotFound.into());
+	}
+
+	let relative_path = dir_path.strip_prefix("/").unwrap();
+	let dir = crate::routes::resolve_path(config, relative_path).await?;
+	// checked where the directory really is, since a symlink in a writable directory could point anywhere in the index
+	let dir = tokio::fs::canonicalize(&dir)
+		.await
+		.map_err(io_ctx("canonicalizing upload directory"))?;
+	let Some(writable) = config.writable(&dir).await else {
+		return Err(error::response(StatusCode::FORBIDDEN, "uploads are not allowed here").into());
+	};
+	let metadata = tokio::fs::metadata(&dir)
+		.await
+		.map_err(io_ctx("reading metadata of upload directory"))?;
+	if !metadata.is_dir() {
+		return Err(error::NotFound.into());
+	}
+
+	Ok((dir, writable))
+}
+
+/// Only plain names are allowed, and no dotfiles if they are excluded, since they could not be seen or downloaded.
+pub(super) fn check_name(
+	config: &Config,
+	name: &str,
+) -> Result<(), error::BadRequest<&'static str>> {
+	if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+		return Err(error::BadRequest("invalid file name"));
+	}
+	if config.exclude_dotfiles && name.starts_with('.') {
+		return Err(error::BadRequest("file names cannot start with a dot"));
+	}
+	Ok(())
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+	headers
+		.get(CONTENT_LENGTH)
+		.and_then(|length| length.to_str().ok())
+		.and_then(|length| length.parse().ok())
+}
+
+/// Write the file to a temporary file in `dir`, then move it into place so that it never appears partially written.
+/// Returns whether an existing file was replaced.
+async fn write<E: Display>(
+	dir: &Path,
+	name: &str,
+	body: impl Stream<Item = Result<Bytes, E>>,
+	budget: &mut u64,
+	replace: bool,
+) -> Result<bool, UploadError> {
+	let temp_path = dir.join(temp_name());
+	let result = match write_temp(&temp_path, body, budget).await {
+		Ok(()) => move_into_place(&temp_path, &dir.join(name), replace).await,
+		Err(error) => Err(error),
+	};
+	if result.is_err() {
+		if let Err(error) = tokio::fs::remove_file(&temp_path).await {
+			if error.kind() != std::io::ErrorKind::NotFound {
+				tracing::warn!(?temp_path, "could not remove temporary upload: {error}");
+			}
+		}
+	}
+	result
+}
+
+/// A dotfile, so it is not listed while it is being written.
+pub(super) fn temp_name() -> String {
+	static COUNTER: AtomicU64 = AtomicU64::new(0);
+	format!(
+		".dexr-upload.{}.{}.tmp",
+		std::process::id(),
+		COUNTER.fetch_add(1, Ordering::Relaxed)
+	)
+}
+
+async fn write_temp<E: Display>(
+	temp_path: &Path,
+	body: impl Stream<Item = Result<Bytes, E>>,
+	budget: &mut u64,
+) -> Result<(), UploadError> {
+	let mut file = tokio::fs::OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(temp_path)
+		.await
+		.map_err(io_ctx("creating temporary upload"))?;
+
+	let mut body = std::pin::pin!(body);
+	while let Some(chunk) = body.next().await {
+		let chunk = chunk.map_err(|error| UploadError::Body(error.to_string()))?;
+		*budget = budget
+			.checked_sub(chunk.len() as u64)
+			.ok_or(UploadError::TooLarge)?;
+		file
+			.write_all(&chunk)
+			.await
+			.map_err(io_ctx("writing upload"))?;
+	}
+
+	file.flush().await.map_err(io_ctx("writing upload"))?;
+	file.sync_all().await.map_err(io_ctx("syncing upload"))?;
+	Ok(())
+}
+
+async fn move_into_place(
+	temp_path: &Path,
+	path: &Path,
+	replace: bool,
+) -> Result<bool, UploadError> {
+	if replace {
+		let replaced = tokio::fs::symlink_metadata(path).await.is_ok();
+		match tokio::fs::rename(temp_path, path).await {
+			Ok(()) => Ok(replaced),
+			// only files can be replaced
+			Err(error) if error.kind() == std::io::ErrorKind::IsADirectory => Err(UploadError::Exists),
+			Err(error) => Err(io_ctx("moving upload into place")(error).into()),
+		}
+	} else {
+		match rename_no_replace(temp_path, path).await {
+			Ok(()) => Ok(false),
+			Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => Err(UploadError::Exists),
+			Err(error) => Err(io_ctx("moving upload into place")(error).into()),
+		}
+	}
+}
+
+/// Rename, but fail with `AlreadyExists` rather than replacing an existing entry, even one created concurrently.
+pub(super) async fn rename_no_replace(from: &Path, to: &Path) -> std::io::Result<()> {
+	let (from, to) = (from.to_owned(), to.to_owned());
+	tokio::task::spawn_blocking(move || {
+		match renameat2(None, &from, None, &to, RenameFlags::RENAME_NOREPLACE) {
+			Ok(()) => Ok(()),
+			// the filesystem does not support the flag
+			Err(Errno::EINVAL | Errno::ENOSYS) => link_no_replace(&from, &to),
+			Err(errno) => Err(errno.into()),
+		}
+	})
+	.await
+	.unwrap_or_else(|error| std::panic::resume_unwind(error.into_panic()))
+}
+
+/// Unlike renaming, linking fails if the destination exists, but it only works for files and not on every filesystem.
+fn link_no_replace(from: &Path, to: &Path) -> std::io::Result<()> {
+	let unsupported = || {
+		std::io::Error::new(
+			std::io::ErrorKind::Unsupported,
+			"the filesystem supports neither renaming without replacing nor hard links",
+		)
+	};
+	if !std::fs::symlink_metadata(from)?.is_file() {
+		return Err(unsupported());
+	}
+	match std::fs::hard_link(from, to) {
+		Ok(()) => {}
+		Err(error)
+			if matches!(
+				error.raw_os_error().map(Errno::from_raw),
+				Some(Errno::EPERM | Errno::EOPNOTSUPP | Errno::ENOSYS)
+			) =>
+		{
+			return Err(unsupported());
+		}
+		Err(error) => return Err(error),
+	}
+	std::fs::remove_file(from)
+}
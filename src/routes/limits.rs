@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::{boxed, StreamBody};
+use axum::middleware::Next;
+use axum::response::{IntoResponse as _, Response};
+use http::{Extensions, HeaderMap, Request};
+use hyper::body::HttpBody as _;
+
+use super::forwarded::{ClientAddr, Proxied};
+use crate::config::Config;
+use crate::error::TooManyRequests;
+
+/// How often buckets that have refilled are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_mins(1);
+
+/// The state of the limits, shared by all requests.
+#[derive(Debug, Default)]
+pub(super) struct Limiters {
+	pub requests: Limiter,
+	pub thumbnails: Limiter,
+	/// For all files being sent together.
+	pub downloads: Mutex<Pacer>,
+}
+
+/// Token buckets for each client, each holding a minute's worth of requests, so that clients can use them in bursts.
+#[derive(Debug)]
+pub(super) struct Limiter {
+	buckets: Mutex<(HashMap<String, Bucket>, Instant)>,
+}
+
+impl Default for Limiter {
+	fn default() -> Self {
+		Self {
+			buckets: Mutex::new((HashMap::new(), Instant::now())),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+impl Bucket {
+	fn refill(&mut self, now: Instant, per_minute: u32) {
+		let capacity = f64::from(per_minute);
+		let elapsed = now.duration_since(self.updated).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * capacity / 60.0).min(capacity);
+		self.updated = now;
+	}
+}
+
+impl Limiter {
+	/// Take a request from the bucket of `client`, or return how long it will be until there is one.
+	pub(super) fn check(&self, client: &str, per_minute: u32) -> Result<(), Duration> {
+		if per_minute == 0 {
+			return Ok(());
+		}
+		let now = Instant::now();
+		let mut guard = self.buckets.lock().unwrap();
+		let (buckets, last_pruned) = &mut *guard;
+
+		if now.duration_since(*last_pruned) >= PRUNE_INTERVAL {
+			buckets.retain(|_, bucket| {
+				bucket.refill(now, per_minute);
+				bucket.tokens < f64::from(per_minute)
+			});
+			*last_pruned = now;
+		}
+
+		let bucket = buckets.entry(client.to_owned()).or_insert(Bucket {
+			tokens: f64::from(per_minute),
+			updated: now,
+		});
+		bucket.refill(now, per_minute);
+		if bucket.tokens >= 1.0 {
+			bucket.tokens -= 1.0;
+			Ok(())
+		} else {
+			Err(Duration::from_secs_f64(
+				(1.0 - bucket.tokens) * 60.0 / f64::from(per_minute),
+			))
+		}
+	}
+}
+
+/// Spaces out chunks so that they are sent at a certain rate on average.
+#[derive(Debug)]
+pub(super) struct Pacer {
+	/// When the next chunk can be sent.
+	next: Instant,
+}
+
+impl Default for Pacer {
+	fn default() -> Self {
+		Self {
+			next: Instant::now(),
+		}
+	}
+}
+
+impl Pacer {
+	/// Returns when a chunk of `len` bytes can be sent.
+	fn reserve(&mut self, len: usize, bytes_per_second: u64, now: Instant) -> Instant {
+		let start = self.next.max(now);
+		let len = u64::try_from(len).unwrap_or(u64::MAX);
+		self.next = start + Duration::from_nanos(len.saturating_mul(1_000_000_000) / bytes_per_second);
+		start
+	}
+}
+
+/// The IP address of the client, or the user named by `user_header` if it is configured and the request came through a trusted proxy.
+/// IPv6 addresses are grouped by their /64 prefix, since that is usually what a single client gets.
+/// Clients whose address is unknown, like over a Unix socket without forwarded headers, cannot be told apart.
+pub(super) fn client<B>(request: &Request<B>, config: &Config) -> Option<String> {
+	if let Some(user) = user(request.extensions(), request.headers(), config) {
+		return Some(format!("user {user}"));
+	}
+
+	let &ClientAddr(addr) = request.extensions().get::<ClientAddr>()?;
+	Some(match addr {
+		IpAddr::V4(ip) => ip.to_string(),
+		IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+			Some(ip) => ip.to_string(),
+			None => format!(
+				"{}/64",
+				Ipv6Addr::from(u128::from(ip) & !u128::from(u64::MAX))
+			),
+		},
+	})
+}
+
+/// The user named by `user_header`, if it is configured and the request came through a trusted proxy.
+pub(super) fn user<'a>(
+	extensions: &Extensions,
+	headers: &'a HeaderMap,
+	config: &Config,
+) -> Option<&'a str> {
+	// anyone else could send a new user with each request, to get a new bucket each time or to pose as someone else
+	extensions.get::<Proxied>()?;
+	let name = config.limits.user_header.as_ref()?;
+	headers.get(name)?.to_str().ok()
+}
+
+/// Limit the number of requests from each client.
+pub(super) async fn requests<B>(request: Request<B>, next: Next<B>) -> Response {
+	let config = Arc::clone(request.extensions().get::<Arc<Config>>().unwrap());
+	let limiters = Arc::clone(request.extensions().get::<Arc<Limiters>>().unwrap());
+
+	if let Some(client) = client(&request, &config) {
+		if let Err(retry_after) = limiters
+			.requests
+			.check(&client, config.limits.requests_per_minute)
+		{
+			tracing::debug!(?client, "rate limited");
+			return TooManyRequests(retry_after).into_response();
+		}
+	}
+	next.run(request).await
+}
+
+/// Send the body of a file no faster than the configured rates.
+pub(super) fn throttle(response: Response, config: &Config, limiters: Arc<Limiters>) -> Response {
+	let own_rate = config.limits.download_bytes_per_second;
+	let total_rate = config.limits.total_download_bytes_per_second;
+	if own_rate == 0 && total_rate == 0 {
+		return response;
+	}
+
+	let (parts, body) = response.into_parts();
+	let body = futures::stream::unfold((body, Pacer::default()), move |(mut body, mut own)| {
+		let limiters = Arc::clone(&limiters);
+		async move {
+			let chunk = match body.data().await? {
+				Ok(chunk) => chunk,
+				Err(error) => return Some((Err(error), (body, own))),
+			};
+			let now = Instant::now();
+			let mut start = now;
+			if own_rate > 0 {
+				start = start.max(own.reserve(chunk.len(), own_rate, now));
+			}
+			if total_rate > 0 {
+				let total = limiters
+					.downloads
+					.lock()
+					.unwrap()
+					.reserve(chunk.len(), total_rate, now);
+				start = start.max(total);
+			}
+			tokio::time::sleep_until(start.into()).await;
+			Some((Ok(chunk), (body, own)))
+		}
+	});
+	Response::from_parts(parts, boxed(StreamBody::new(body)))
+}
+
+#[test]
+fn test_limiter() {
+	let limiter = Limiter::default();
+	for _ in 0..3 {
+		assert!(limiter.check("a", 3).is_ok());
+	}
+	let retry_after = limiter.check("a", 3).unwrap_err();
+	assert!(retry_after > Duration::from_secs(19) && retry_after <= Duration::from_secs(20));
+	// other clients have their own buckets
+	assert!(limiter.check("b", 3).is_ok());
+}
@@ -1,24 +1,109 @@
-use axum::response::Redirect;
-use axum::routing::{get, get_service};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::response::{ErrorResponse, Redirect};
+use axum::routing::get;
 use axum::Router;
+use http::header::CONTENT_TYPE;
+use http::{Extensions, HeaderMap, StatusCode, Version};
+use tower_http::compression::predicate::{DefaultPredicate, Predicate};
+use tower_http::compression::CompressionLayer;
+
+use crate::config::{Config, Symlinks};
 
+mod caching;
+mod errors;
+mod forwarded;
 mod fs;
+mod limits;
+mod preview;
+mod security;
+mod share;
+mod static_files;
 mod thumbnail;
 
-pub fn configure() -> Router {
+pub fn configure(config: &Config) -> Router {
 	let mut router = Router::new();
 
-	router = router.route("/", get(|| async { Redirect::permanent("/fs/") }));
+	let root = Redirect::permanent(&format!("{}/fs/", config.base_path));
+	router = router.route("/", get(move || std::future::ready(root.clone())));
 	router = router.nest("/thumb", thumbnail::configure());
 	router = router.nest("/fs", fs::configure());
-	router = router.nest("/static", get_service(static_router())); // work around axum special-casing nesting `Router`s
+	router = router.nest("/preview", preview::configure());
+	router = router.nest("/dav", fs::dav::configure());
+	router = router.nest("/s", share::service());
+	router = router.nest("/static", static_files::configure());
+
+	router = router
+		.layer(axum::Extension(Mount {
+			base: config.base_path.clone(),
+			path: PathBuf::new(),
+		}))
+		.layer(axum::middleware::from_fn(security::same_origin))
+		.layer(axum::middleware::from_fn(limits::requests))
+		.layer(axum::Extension(Arc::new(limits::Limiters::default())))
+		.layer(axum::Extension(Arc::new(fs::MediaCache::default())))
+		// shared by listings, shares, and DAV, so that each tree is only walked once
+		.layer(axum::Extension(Arc::new(fs::DirSizes::default())))
+		.layer(axum::middleware::from_fn(forwarded::client_addr))
+		.layer(axum::middleware::from_fn(errors::render))
+		.layer(CompressionLayer::new().compress_when(should_compress()))
+		.layer(axum::middleware::from_fn(security::headers));
+
+	// the prefix is taken out of the URL before the layers above see it
+	if config.base_path.is_empty() {
+		router
+	} else {
+		Router::new().nest(&config.base_path, router)
+	}
+}
+
+/// Where the routes for listings, thumbnails, and previews are, which differs within shares.
+#[derive(Debug, Clone)]
+pub struct Mount {
+	/// Prepended to the URLs of the routes, including `base_path`. Empty, or starting with a slash and without a trailing slash.
+	pub base: String,
+	/// The path of `index_root` within the real one, which is empty outside of shares.
+	pub path: PathBuf,
+}
 
-	router
+/// Images (including thumbnails) are already excluded by the default predicate.
+fn should_compress() -> impl Predicate {
+	DefaultPredicate::new().and(
+		|status: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions| {
+			// compressing part of a file would make the range meaningless
+			status != StatusCode::PARTIAL_CONTENT
+				&& !headers
+					.get(CONTENT_TYPE)
+					.and_then(|content_type| content_type.to_str().ok())
+					.is_some_and(is_compressed_type)
+		},
+	)
 }
 
-static_router::static_router!(static_router, "static");
+/// Media and archives, which are already compressed.
+fn is_compressed_type(content_type: &str) -> bool {
+	const PREFIXES: [&str; 12] = [
+		"video/",
+		"audio/",
+		"font/woff",
+		"application/pdf",
+		"application/zip",
+		"application/gzip",
+		"application/zstd",
+		"application/vnd.rar",
+		"application/x-7z-compressed",
+		"application/x-bzip2",
+		"application/x-rar-compressed",
+		"application/x-xz",
+	];
 
-fn is_hidden_path(path: &std::path::Path) -> bool {
+	PREFIXES
+		.iter()
+		.any(|prefix| content_type.starts_with(prefix))
+}
+
+fn is_hidden_path(path: &Path) -> bool {
 	path
 		.components()
 		.any(|component| starts_with_dot(component.as_os_str()))
@@ -30,7 +115,34 @@ fn starts_with_dot(name: &std::ffi::OsStr) -> bool {
 		.map_or(false, |&byte| byte == b'.')
 }
 
-fn assert_path_safe(path: &std::path::Path) -> Result<(), axum::response::ErrorResponse> {
+/// Find `relative_path` within `index_root`, responding with not found if getting there would follow a symlink that the symlink policy forbids.
+/// The returned path is not canonicalized, so it keeps the name of the symlink if there is one.
+async fn resolve_path(config: &Config, relative_path: &Path) -> Result<PathBuf, ErrorResponse> {
+	// joining an empty path would add a trailing slash, which fails if the root is a file
+	let fs_path = if relative_path.as_os_str().is_empty() {
+		config.index_root.clone()
+	} else {
+		config.index_root.join(relative_path)
+	};
+	if matches!(config.symlinks, Symlinks::Follow) {
+		return Ok(fs_path);
+	}
+
+	let canonical = tokio::fs::canonicalize(&fs_path)
+		.await
+		.map_err(crate::error::io_ctx("canonicalizing path"))?;
+	let through_link = canonical != fs_path;
+	if config
+		.symlinks
+		.allows(&config.index_root, &canonical, through_link)
+	{
+		Ok(fs_path)
+	} else {
+		Err(crate::error::NotFound.into())
+	}
+}
+
+fn assert_path_safe(path: &Path) -> Result<(), ErrorResponse> {
 	use std::path::Component;
 
 	if !path.is_absolute()
@@ -0,0 +1,101 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::extract;
+use axum::response::{ErrorResponse, IntoResponse, Response};
+use axum::routing::{get, Router};
+use http::StatusCode;
+use tokio::io::AsyncReadExt as _;
+
+use super::Mount;
+use crate::config::Config;
+use crate::error::{self, io_ctx};
+use crate::util::encode_path;
+
+/// Renders an HTML fragment previewing a text file, to be shown in the preview overlay.
+async fn handler(
+	extract::Path(user_path): extract::Path<PathBuf>,
+	extract::Extension(config): extract::Extension<Arc<Config>>,
+	extract::Extension(mount): extract::Extension<Mount>,
+) -> Result<Response, ErrorResponse> {
+	super::assert_path_safe(&user_path)?;
+
+	if config.exclude_dotfiles && super::is_hidden_path(&user_path) {
+		return Ok(error::NotFound.into_response());
+	}
+
+	// classified the same way as in listings, which only link to previews of text files
+	let is_text = user_path
+		.extension()
+		.and_then(std::ffi::OsStr::to_str)
+		.and_then(|extension| crate::thumbnail::Type::for_extension(extension, &config.thumbnailers))
+		.is_some_and(|ty| matches!(ty, crate::thumbnail::Type::Text));
+	if !is_text {
+		return Err(
+			error::response(
+				StatusCode::UNSUPPORTED_MEDIA_TYPE,
+				"only text files can be previewed",
+			)
+			.into(),
+		);
+	}
+
+	let relative_path = user_path.strip_prefix("/").unwrap();
+	let fs_path = super::resolve_path(&config, relative_path).await?;
+	let file = tokio::fs::File::open(&fs_path)
+		.await
+		.map_err(io_ctx("opening file"))?;
+	let metadata = file.metadata().await.map_err(io_ctx("reading metadata"))?;
+	if !metadata.is_file() {
+		return Ok(error::NotFound.into_response());
+	}
+
+	let max_bytes = config.text_preview_max_bytes;
+	let mut contents = Vec::new();
+	file
+		.take(max_bytes)
+		.read_to_end(&mut contents)
+		.await
+		.map_err(io_ctx("reading file"))?;
+	let truncated = metadata.len() > max_bytes;
+
+	// encoded, since relative links are appended to it as they are
+	let dir_url = format!(
+		"{}/fs{}",
+		mount.base,
+		encode_path(user_path.parent().unwrap_or(&user_path))
+	);
+	let mut html = tokio_rayon::spawn(move || {
+		let contents = String::from_utf8_lossy(&contents);
+		let extension = crate::markup::lowercase_extension(&fs_path);
+		match extension.as_deref() {
+			Some("md" | "markdown") => format!(
+				"<article class=\"markdown\">{}</article>",
+				crate::markup::markdown(&contents, &dir_url)
+			),
+			extension => crate::markup::highlight(&contents, extension),
+		}
+	})
+	.await;
+
+	if truncated {
+		write!(
+			html,
+			"<p class=\"truncated\">Only the first {max_bytes} bytes are shown.</p>"
+		)
+		.unwrap();
+	}
+
+	Ok(
+		(
+			[(http::header::CONTENT_TYPE, "text/html; charset=utf-8")],
+			html,
+		)
+			.into_response(),
+	)
+}
+
+pub fn configure() -> Router {
+	Router::new().route("/*path", get(handler))
+}
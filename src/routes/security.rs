@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use axum::middleware::Next;
+use axum::response::Response;
+use http::header::{
+	HeaderName, CONTENT_SECURITY_POLICY, CONTENT_TYPE, HOST, ORIGIN, REFERRER_POLICY,
+	X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use http::{HeaderMap, HeaderValue, Request, StatusCode};
+
+use super::forwarded::Proxied;
+use crate::config::Config;
+
+const SEC_FETCH_SITE: HeaderName = HeaderName::from_static("sec-fetch-site");
+
+/// Add the configured security headers to responses that do not already have them.
+pub(super) async fn headers<B>(request: Request<B>, next: Next<B>) -> Response {
+	let config = Arc::clone(request.extensions().get::<Arc<Config>>().unwrap());
+	let mut response = next.run(request).await;
+	let security = &config.security_headers;
+	let headers = response.headers_mut();
+
+	// otherwise browsers may decide that a text file is actually HTML
+	insert_default(headers, X_CONTENT_TYPE_OPTIONS, "nosniff");
+	insert_default(headers, REFERRER_POLICY, &security.referrer_policy);
+	insert_default(headers, X_FRAME_OPTIONS, &security.frame_options);
+	// user files get their own policy, if any, in `fs::file`
+	let is_html = headers
+		.get(CONTENT_TYPE)
+		.and_then(|content_type| content_type.to_str().ok())
+		.is_some_and(|content_type| content_type.starts_with("text/html"));
+	if is_html {
+		insert_default(
+			headers,
+			CONTENT_SECURITY_POLICY,
+			&security.content_security_policy,
+		);
+	}
+
+	response
+}
+
+/// Reject requests that change files if they come from another site, so that other sites cannot make visitors change files.
+/// Clients other than browsers do not send these headers, so they are allowed.
+/// Behind a trusted proxy, `Origin` is compared with the host that the proxy forwarded, since `Host` is the address of dexr itself.
+pub(super) async fn same_origin<B>(request: Request<B>, next: Next<B>) -> Response {
+	let forwarded_host = request
+		.extensions()
+		.get::<Proxied>()
+		.and_then(|proxied| proxied.host.as_deref());
+	if request.method().is_safe() || is_same_origin(request.headers(), forwarded_host) {
+		next.run(request).await
+	} else {
+		crate::error::response(StatusCode::FORBIDDEN, "cross-origin request")
+	}
+}
+
+fn is_same_origin(headers: &HeaderMap, forwarded_host: Option<&str>) -> bool {
+	let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+	if let Some(site) = header(SEC_FETCH_SITE) {
+		// `none` is for requests that the user made themself, like by entering the URL
+		return matches!(site, "same-origin" | "none");
+	}
+	match (header(ORIGIN), forwarded_host.or_else(|| header(HOST))) {
+		(Some(origin), Some(host)) => origin
+			.split_once("://")
+			.is_some_and(|(_scheme, authority)| authority == host),
+		(Some(_), None) => false,
+		(None, _) => true,
+	}
+}
+
+fn insert_default(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+	if value.is_empty() || headers.contains_key(&name) {
+		return;
+	}
+	if let Ok(value) = HeaderValue::from_str(value) {
+		headers.insert(name, value);
+	}
+}
@@ -0,0 +1,191 @@
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::extract::{Form, FromRequest as _, RequestParts};
+use axum::response::{ErrorResponse, IntoResponse as _, Redirect, Response};
+use axum::Router;
+use futures::Future;
+use http::header::{COOKIE, SET_COOKIE};
+use http::{HeaderMap, Method, Request, StatusCode, Uri};
+use hyper::service::Service;
+use hyper::Body;
+use serde::Deserialize;
+
+use super::Mount;
+use crate::config::Config;
+use crate::error::{self, io_ctx};
+use crate::share::{self, Invalid, Share};
+
+const UNLOCK_COOKIE: &str = "dexr_share";
+
+/// `/s/<token>/...`, with the same routes as the root for listings, thumbnails, and previews, but only for the shared path.
+/// This is a service rather than a router, so that the token can be taken out of the URL before routing.
+pub(super) fn service() -> impl Service<
+	Request<Body>,
+	Response = Response,
+	Error = Infallible,
+	Future = impl Future<Output = Result<Response, Infallible>> + Send,
+> + Clone
+     + Send {
+	let routes = Router::new()
+		.nest("/fs", super::fs::configure())
+		.nest("/thumb", super::thumbnail::configure())
+		.nest("/preview", super::preview::configure());
+	hyper::service::service_fn(move |request| {
+		let routes = routes.clone();
+		async move { Ok(scope(request, routes).await.into_response()) }
+	})
+}
+
+async fn scope(mut request: Request<Body>, mut routes: Router) -> Result<Response, ErrorResponse> {
+	let config = Arc::clone(request.extensions().get::<Arc<Config>>().unwrap());
+	let Some(secret) = &config.share_secret else {
+		return Err(error::NotFound.into());
+	};
+
+	// the path is after `/s`
+	let path = request.uri().path().trim_start_matches('/');
+	let (token, rest) = path.split_once('/').unwrap_or((path, ""));
+	let token = token.to_owned();
+	let base = format!(
+		"{}/s/{token}",
+		request.extensions().get::<Mount>().unwrap().base
+	);
+	let rest = match request.uri().query() {
+		Some(query) => format!("/{rest}?{query}"),
+		None => format!("/{rest}"),
+	};
+
+	let now = time::OffsetDateTime::now_utc().unix_timestamp();
+	let share = match Share::verify(secret, &token, now) {
+		Ok(share) => share,
+		Err(Invalid::Expired) => {
+			return Err(error::response(StatusCode::GONE, "this link has expired").into());
+		}
+		Err(invalid) => {
+			tracing::debug!(?token, "invalid share link: {invalid}");
+			return Err(error::NotFound.into());
+		}
+	};
+	if share.has_password() && !is_unlocked(request.headers(), secret, &token) {
+		return Ok(unlock(request, &config, &token, &share, &base, &rest).await);
+	}
+	if rest == "/" {
+		return Ok(Redirect::to(&format!("{base}/fs/")).into_response());
+	}
+
+	let (config, path) = scoped_config(&config, &share).await?;
+	*request.uri_mut() = Uri::try_from(rest).map_err(|_| error::BadRequest("invalid URL"))?;
+	request.extensions_mut().insert(Arc::new(config));
+	request.extensions_mut().insert(Mount { base, path });
+	Ok(
+		routes
+			.call(request)
+			.await
+			.unwrap_or_else(|never| match never {}),
+	)
+}
+
+/// A copy of the config with `index_root` narrowed to the shared path, where nothing can be changed.
+/// Also returns the path of the new `index_root` within the real one.
+async fn scoped_config(config: &Config, share: &Share) -> Result<(Config, PathBuf), ErrorResponse> {
+	super::assert_path_safe(&share.path)?;
+	if config.exclude_dotfiles && super::is_hidden_path(&share.path) {
+		return Err(error::NotFound.into());
+	}
+
+	let relative_path = share.path.strip_prefix("/").unwrap();
+	let fs_path = super::resolve_path(config, relative_path).await?;
+	// so that symlinks can be checked against it, like the real one
+	let root = tokio::fs::canonicalize(&fs_path)
+		.await
+		.map_err(io_ctx("canonicalizing shared path"))?;
+	let path = root
+		.strip_prefix(&config.index_root)
+		.map_or_else(|_| relative_path.to_owned(), Path::to_owned);
+
+	Ok((
+		Config {
+			index_root: root,
+			writable: Vec::new(),
+			..config.clone()
+		},
+		path,
+	))
+}
+
+fn is_unlocked(headers: &HeaderMap, secret: &str, token: &str) -> bool {
+	headers
+		.get_all(COOKIE)
+		.iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(';'))
+		.filter_map(|cookie| cookie.trim().split_once('='))
+		.any(|(name, key)| name == UNLOCK_COOKIE && share::check_unlock_key(secret, token, key))
+}
+
+#[derive(Deserialize)]
+struct Unlock {
+	password: String,
+}
+
+/// Ask for the password, and once it is entered, set a cookie that is sent with every request within the share.
+/// `base` is the URL of the share, and `rest` is the requested URL within it.
+async fn unlock(
+	request: Request<Body>,
+	config: &Config,
+	token: &str,
+	share: &Share,
+	base: &str,
+	rest: &str,
+) -> Response {
+	let secret = config.share_secret.as_deref().unwrap();
+	if request.method() != Method::POST {
+		return password_page(config, None);
+	}
+
+	let Form(Unlock { password }) = match Form::from_request(&mut RequestParts::new(request)).await {
+		Ok(form) => form,
+		Err(rejection) => return rejection.into_response(),
+	};
+	if !share.check_password(secret, &password) {
+		return password_page(config, Some("Wrong password."));
+	}
+
+	let max_age = share.expires - time::OffsetDateTime::now_utc().unix_timestamp();
+	let cookie = format!(
+		"{UNLOCK_COOKIE}={}; Path={base}/; Max-Age={max_age}; HttpOnly; SameSite=Lax",
+		share::unlock_key(secret, token),
+	);
+	(
+		[(SET_COOKIE, cookie)],
+		Redirect::to(&format!("{base}{rest}")),
+	)
+		.into_response()
+}
+
+/// An HTML page, so it is not replaced like other errors.
+fn password_page(config: &Config, problem: Option<&str>) -> Response {
+	let mut body = String::new();
+	super::fs::write_document_start(&mut body, &config.base_path, "Password required", None);
+	write!(
+		body,
+		"<h1>Password required</h1>\
+		{}\
+		<form method=\"post\">\
+			<label>Password: <input type=\"password\" name=\"password\" required autofocus></label> \
+			<button type=\"submit\">Open</button>\
+		</form>\
+		</body></html>",
+		problem.map_or_else(String::new, |problem| format!("<p>{problem}</p>")),
+	)
+	.unwrap();
+	(
+		StatusCode::FORBIDDEN,
+		[("Content-Type", "text/html; charset=utf-8")],
+		body,
+	)
+		.into_response()
+}
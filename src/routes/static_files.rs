@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use axum::extract;
+use axum::response::{IntoResponse as _, Response};
+use axum::routing::{get, Router};
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, VARY};
+use http::{HeaderMap, HeaderValue};
+
+use super::caching::{set_cache_control, Validators};
+use crate::config::Config;
+
+/// A file from the `static` directory, embedded by the build script.
+struct StaticFile {
+	name: &'static str,
+	content_type: &'static str,
+	/// The MD5 hash of the contents.
+	hash: &'static str,
+	contents: &'static [u8],
+	/// Precompressed variants, keyed by their content coding, in order of preference.
+	encoded: &'static [(&'static str, &'static [u8])],
+}
+
+static FILES: &[StaticFile] = include!(concat!(env!("OUT_DIR"), "/static_files.rs"));
+
+async fn handler(
+	extract::Path(name): extract::Path<String>,
+	extract::Extension(config): extract::Extension<Arc<Config>>,
+	headers: HeaderMap,
+) -> Response {
+	let name = name.trim_start_matches('/');
+	let Some(file) = FILES.iter().find(|file| file.name == name) else {
+		return crate::error::NotFound.into_response();
+	};
+
+	let (encoding, contents) = file
+		.encoded
+		.iter()
+		.find(|(encoding, _)| accepts_encoding(&headers, encoding))
+		.map_or((None, file.contents), |&(encoding, contents)| {
+			(Some(encoding), contents)
+		});
+
+	// each encoding is a different representation, so it needs a different tag
+	let key = format!("{}\0{}", file.hash, encoding.unwrap_or("identity"));
+	let validators = Validators::new(&key, false, None);
+	let mut response = if validators.is_fresh(&headers) {
+		validators.not_modified()
+	} else {
+		let mut response = (
+			[(CONTENT_TYPE, HeaderValue::from_static(file.content_type))],
+			contents,
+		)
+			.into_response();
+		if let Some(encoding) = encoding {
+			response
+				.headers_mut()
+				.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+		}
+		validators.apply(&mut response);
+		response
+	};
+	if !file.encoded.is_empty() {
+		response
+			.headers_mut()
+			.insert(VARY, HeaderValue::from_static("accept-encoding"));
+	}
+	set_cache_control(&mut response, &config.cache_control.static_files);
+	response
+}
+
+/// Whether `Accept-Encoding` allows `encoding`, ignoring preferences other than refusals with `q=0`.
+fn accepts_encoding(headers: &HeaderMap, encoding: &str) -> bool {
+	headers
+		.get_all(ACCEPT_ENCODING)
+		.iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.any(|item| {
+			let mut parts = item.split(';').map(str::trim);
+			let name = parts.next().unwrap_or_default();
+			let refused = parts.any(|param| {
+				param
+					.strip_prefix("q=")
+					.and_then(|q| q.parse::<f32>().ok())
+					.is_some_and(|q| q <= 0.0)
+			});
+			!refused && (name.eq_ignore_ascii_case(encoding) || name == "*")
+		})
+}
+
+#[test]
+fn test_accepts_encoding() {
+	let headers = |value| {
+		let mut headers = HeaderMap::new();
+		headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(value));
+		headers
+	};
+
+	assert!(accepts_encoding(&headers("gzip, deflate, br"), "br"));
+	assert!(accepts_encoding(&headers("GZIP;q=0.5"), "gzip"));
+	assert!(!accepts_encoding(&headers("gzip, br;q=0"), "br"));
+	assert!(accepts_encoding(&headers("*"), "zstd"));
+	assert!(!accepts_encoding(&headers("identity"), "gzip"));
+	assert!(!accepts_encoding(&HeaderMap::new(), "gzip"));
+}
+
+pub fn configure() -> Router {
+	Router::new().route("/*path", get(handler))
+}
@@ -1,7 +1,6 @@
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use axum::error_handling::HandleError;
 use axum::extract;
 use axum::response::{ErrorResponse, IntoResponse, Redirect, Response};
 use axum::routing::{get, Router};
@@ -9,23 +8,27 @@ use http::Request;
 use hyper::service::Service;
 use hyper::Body;
 
+use super::caching::{set_cache_control, Validators};
+use super::limits::{self, Limiters};
+use super::Mount;
 use crate::config::Config;
 use crate::error;
 
 async fn handler(
 	extract::Path(user_path): extract::Path<PathBuf>,
 	extract::Extension(config): extract::Extension<Arc<Config>>,
-	extract::Extension(thumbnail_state): extract::Extension<Arc<crate::thumbnail::State>>,
+	extract::Extension(mount): extract::Extension<Mount>,
+	extract::Extension(limiters): extract::Extension<Arc<Limiters>>,
 	req: Request<Body>,
 ) -> Result<Response, ErrorResponse> {
 	super::assert_path_safe(&user_path)?;
 
 	if config.exclude_dotfiles && super::is_hidden_path(&user_path) {
-		return Ok(http::StatusCode::NOT_FOUND.into_response());
+		return Ok(error::NotFound.into_response());
 	}
 
 	let relative_path = user_path.strip_prefix("/").unwrap();
-	let fs_path = config.index_root.join(relative_path);
+	let fs_path = super::resolve_path(&config, relative_path).await?;
 	let fs_path = tokio::fs::canonicalize(fs_path)
 		.await
 		.map_err(|error| error::Io {
@@ -33,72 +36,102 @@ async fn handler(
 			error,
 		})?;
 
-	// if possible, redirect to the target of the symlink to avoid generating multiple identical thumbnails
+	// if the target of the symlink is within the root, redirect to it to avoid generating multiple identical thumbnails
 	if let Ok(canonical_user_path) = fs_path.strip_prefix(&config.index_root) {
 		if canonical_user_path != relative_path {
 			return Ok(
-				Redirect::temporary(&crate::util::join_paths([
-					"/thumb",
-					&canonical_user_path.to_string_lossy(),
-				]))
+				Redirect::temporary(&format!(
+					"{}/thumb{}",
+					mount.base,
+					crate::util::encode_path(canonical_user_path)
+				))
 				.into_response(),
 			);
 		}
 	}
 
-	let encoded_path = crate::util::encode_relative_path(relative_path);
-	tokio::fs::create_dir_all(&config.thumbnail_tmp)
-		.await
-		.map_err(error::io_ctx("ensuring existence of thumbnail directory"))?;
-	let thumbnail_path = config.thumbnail_tmp.join(format!("{encoded_path}.png"));
+	let Some(ty) = fs_path
+		.extension()
+		.and_then(std::ffi::OsStr::to_str)
+		.and_then(|extension| crate::thumbnail::Type::for_extension(extension, &config.thumbnailers))
+	else {
+		return Ok((&crate::thumbnail::GenerateError::NotRich).into_response());
+	};
 
 	let fs_path = Arc::from(fs_path.into_boxed_path());
-	let thumbnail_path = Arc::from(thumbnail_path.into_boxed_path());
 
-	if let Err(error) = crate::thumbnail::generate(
-		thumbnail_state,
+	// relative to the real `index_root` within shares, so that thumbnails are shared with the rest of the index
+	let cache_path = if relative_path.as_os_str().is_empty() {
+		mount.path.clone()
+	} else {
+		mount.path.join(relative_path)
+	};
+	let client = limits::client(&req, &config);
+	let permit = || match &client {
+		Some(client) => limiters
+			.thumbnails
+			.check(client, config.limits.thumbnails_per_minute),
+		None => Ok(()),
+	};
+	let thumbnail = crate::thumbnail::generate(
+		Arc::clone(&config),
+		ty,
 		Arc::clone(&fs_path),
-		Arc::clone(&thumbnail_path),
+		&cache_path,
+		permit,
 	)
-	.await
-	{
-		tracing::error!(
-			?fs_path,
-			?thumbnail_path,
-			"thumbnail creation failed: {error:?}"
-		);
-		if let crate::thumbnail::GenerateError::Io { context, error } = &*error {
-			return Err(error::Io { context, error }.into());
+	.await;
+	let thumbnail_path = match thumbnail {
+		Ok(crate::thumbnail::Thumbnail::Generated(thumbnail_path)) => thumbnail_path,
+		Ok(crate::thumbnail::Thumbnail::Failed(failure)) => return Ok(failure.into_response()),
+		Err(error @ crate::thumbnail::GenerateError::RateLimited(_)) => {
+			return Ok((&error).into_response())
 		}
-	}
+		Err(error) => {
+			tracing::error!(?fs_path, "thumbnail creation failed: {error:?}");
+			return Ok((&error).into_response());
+		}
+	};
 
-	Ok(
-		<HandleError<_, _, ()> as Service<_>>::call(
-			&mut HandleError::new(
-				tower_http::services::ServeFile::new_with_mime(thumbnail_path, &mime::IMAGE_PNG),
-				|error| async {
-					error::Io {
-						context: "serving file",
-						error,
-					}
-				},
-			),
-			req,
-		)
-		.await
-		.unwrap_or_else(|never| match never {}), // infallible
-	)
+	let (source_metadata, thumbnail_metadata) = tokio::join!(
+		tokio::fs::metadata(&fs_path),
+		tokio::fs::metadata(&thumbnail_path)
+	);
+	let key = format!(
+		"{}\0{:?}\0{:?}",
+		thumbnail_path.display(),
+		source_metadata
+			.map_err(error::io_ctx("reading metadata of source file"))?
+			.modified()
+			.ok(),
+		thumbnail_metadata
+			.map_err(error::io_ctx("reading metadata of thumbnail"))?
+			.modified()
+			.ok(),
+	);
+	// `ServeFile` sets `Last-Modified` from the thumbnail itself
+	let validators = Validators::new(&key, false, None);
+
+	let mut response = if validators.is_fresh(req.headers()) {
+		validators.not_modified()
+	} else {
+		let mut response =
+			tower_http::services::ServeFile::new_with_mime(thumbnail_path, &mime::IMAGE_PNG)
+				.call(req)
+				.await
+				.unwrap_or_else(|never| match never {})
+				.map(axum::body::boxed);
+		validators.apply(&mut response);
+		response
+	};
+	set_cache_control(&mut response, &config.cache_control.thumbnails);
+	Ok(response)
 }
 
 pub fn configure() -> Router {
 	let mut router = Router::new();
 
-	router = router.route(
-		"/*path",
-		get(handler).layer(extract::Extension(Arc::new(
-			crate::thumbnail::State::default(),
-		))),
-	);
+	router = router.route("/*path", get(handler));
 
 	router
 }
@@ -1,3 +1,4 @@
+use std::net::SocketAddr;
 use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -50,7 +51,8 @@ pub async fn serve(mut app: Router, config: Config) -> Result<()> {
 	app = app.layer(axum::Extension(Arc::clone(&config)));
 	match &config.address {
 		BindableAddr::Tcp(addr) => Server::bind(addr)
-			.serve(app.into_make_service())
+			// for rate limits
+			.serve(app.into_make_service_with_connect_info::<SocketAddr>())
 			.await
 			.context("starting server"),
 		BindableAddr::Unix(addr) => Server::builder(UnixAccept::new(addr)?)
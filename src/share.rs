@@ -0,0 +1,236 @@
+use std::io::BufRead as _;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context as _, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config::Config;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a share link gives access to.
+/// Tokens are signed but not encrypted, so anyone with the link can see the shared path and the expiry.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Share {
+	/// As in URLs.
+	pub path: PathBuf,
+	/// Seconds since the Unix epoch.
+	pub expires: i64,
+	/// A MAC of the password, which cannot be checked without the secret.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	password: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Invalid {
+	#[error("malformed token")]
+	Malformed,
+	#[error("invalid signature")]
+	Signature,
+	#[error("expired")]
+	Expired,
+}
+
+fn encode(bytes: impl AsRef<[u8]>) -> String {
+	base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(encoded: &str) -> Option<Vec<u8>> {
+	base64::decode_config(encoded, base64::URL_SAFE_NO_PAD).ok()
+}
+
+/// Separate purposes, so that a MAC for one can never be used as another.
+fn mac(secret: &str, purpose: &str, data: &[u8]) -> HmacSha256 {
+	let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+	mac.update(purpose.as_bytes());
+	mac.update(b"\0");
+	mac.update(data);
+	mac
+}
+
+fn verify(secret: &str, purpose: &str, data: &[u8], encoded_mac: &str) -> bool {
+	decode(encoded_mac)
+		.is_some_and(|expected| mac(secret, purpose, data).verify_slice(&expected).is_ok())
+}
+
+impl Share {
+	pub fn new(secret: &str, path: PathBuf, expires: i64, password: Option<&str>) -> Self {
+		Self {
+			path,
+			expires,
+			password: password.map(|password| {
+				encode(
+					mac(secret, "password", password.as_bytes())
+						.finalize()
+						.into_bytes(),
+				)
+			}),
+		}
+	}
+
+	pub fn token(&self, secret: &str) -> String {
+		let payload = serde_json::to_vec(self).unwrap();
+		let signature = mac(secret, "token", &payload).finalize().into_bytes();
+		format!("{}.{}", encode(&payload), encode(signature))
+	}
+
+	/// `now` is in seconds since the Unix epoch.
+	pub fn verify(secret: &str, token: &str, now: i64) -> Result<Self, Invalid> {
+		let (payload, signature) = token.split_once('.').ok_or(Invalid::Malformed)?;
+		let payload = decode(payload).ok_or(Invalid::Malformed)?;
+		if !verify(secret, "token", &payload, signature) {
+			return Err(Invalid::Signature);
+		}
+		let share: Self = serde_json::from_slice(&payload).map_err(|_| Invalid::Malformed)?;
+		if share.expires <= now {
+			return Err(Invalid::Expired);
+		}
+		Ok(share)
+	}
+
+	pub fn has_password(&self) -> bool {
+		self.password.is_some()
+	}
+
+	pub fn check_password(&self, secret: &str, password: &str) -> bool {
+		self
+			.password
+			.as_ref()
+			.is_none_or(|expected| verify(secret, "password", password.as_bytes(), expected))
+	}
+}
+
+/// Proves that the password of the share with `token` was entered, so that it is not needed for every request.
+pub fn unlock_key(secret: &str, token: &str) -> String {
+	encode(
+		mac(secret, "unlock", token.as_bytes())
+			.finalize()
+			.into_bytes(),
+	)
+}
+
+pub fn check_unlock_key(secret: &str, token: &str, key: &str) -> bool {
+	verify(secret, "unlock", token.as_bytes(), key)
+}
+
+/// `dexr share <path> [--expires <duration>] [--password]`, which prints the path of the link.
+/// With `--password`, the password is read from the first line of standard input.
+pub fn command(config: &Config, mut args: impl Iterator<Item = String>) -> Result<()> {
+	let Some(secret) = &config.share_secret else {
+		bail!("sharing is disabled, since `share_secret` is not set");
+	};
+
+	let mut path = None;
+	let mut expires_in = 7 * 24 * 60 * 60;
+	let mut with_password = false;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--expires" => {
+				let duration = args.next().context("missing duration after `--expires`")?;
+				expires_in =
+					parse_duration(&duration).with_context(|| format!("invalid duration {duration:?}"))?;
+			}
+			"--password" => with_password = true,
+			_ if arg.starts_with("--") => bail!("unknown option {arg:?}"),
+			_ if path.is_none() => path = Some(PathBuf::from(arg)),
+			_ => bail!("unexpected argument {arg:?}"),
+		}
+	}
+	let path = path.context("missing path to share")?;
+	if !is_plain_absolute(&path) {
+		bail!("the path must start with a slash and cannot contain `.` or `..`, as in URLs");
+	}
+	let fs_path = config.index_root.join(path.strip_prefix("/").unwrap());
+	std::fs::metadata(&fs_path)
+		.with_context(|| format!("reading metadata of {}", fs_path.display()))?;
+
+	let password = if with_password {
+		let mut password = String::new();
+		std::io::stdin()
+			.lock()
+			.read_line(&mut password)
+			.context("reading password")?;
+		let password = password.trim_end_matches(['\r', '\n']).to_owned();
+		if password.is_empty() {
+			bail!("the password cannot be empty");
+		}
+		Some(password)
+	} else {
+		None
+	};
+
+	let Some(expires) = time::OffsetDateTime::now_utc()
+		.unix_timestamp()
+		.checked_add(expires_in)
+	else {
+		bail!("the expiry is too far in the future");
+	};
+	let share = Share::new(secret, path, expires, password.as_deref());
+	println!("{}/s/{}/", config.base_path, share.token(secret));
+	Ok(())
+}
+
+fn is_plain_absolute(path: &Path) -> bool {
+	path.is_absolute()
+		&& path
+			.components()
+			.all(|component| matches!(component, Component::RootDir | Component::Normal(_)))
+}
+
+/// In seconds, from a number with an optional unit, such as `90`, `30m`, or `7d`.
+fn parse_duration(duration: &str) -> Option<i64> {
+	let (number, unit) = match duration.find(|ch: char| !ch.is_ascii_digit()) {
+		Some(index) => duration.split_at(index),
+		None => (duration, "s"),
+	};
+	let multiplier = match unit {
+		"s" => 1,
+		"m" => 60,
+		"h" => 60 * 60,
+		"d" => 24 * 60 * 60,
+		"w" => 7 * 24 * 60 * 60,
+		_ => return None,
+	};
+	number.parse::<i64>().ok()?.checked_mul(multiplier)
+}
+
+#[test]
+fn test_token() {
+	let share = Share::new("secret", PathBuf::from("/photos"), 100, Some("hunter2"));
+	let token = share.token("secret");
+
+	let verified = Share::verify("secret", &token, 50).unwrap();
+	assert_eq!(verified, share);
+	assert!(verified.check_password("secret", "hunter2"));
+	assert!(!verified.check_password("secret", "hunter3"));
+
+	assert!(matches!(
+		Share::verify("secret", &token, 100),
+		Err(Invalid::Expired)
+	));
+	assert!(matches!(
+		Share::verify("other secret", &token, 50),
+		Err(Invalid::Signature)
+	));
+	let (_, signature) = token.split_once('.').unwrap();
+	let forged = Share::new("secret", PathBuf::from("/"), 100, None);
+	let forged = format!(
+		"{}.{signature}",
+		forged.token("secret").split_once('.').unwrap().0
+	);
+	assert!(matches!(
+		Share::verify("secret", &forged, 50),
+		Err(Invalid::Signature)
+	));
+}
+
+#[test]
+fn test_parse_duration() {
+	assert_eq!(parse_duration("90"), Some(90));
+	assert_eq!(parse_duration("30m"), Some(30 * 60));
+	assert_eq!(parse_duration("7d"), Some(7 * 24 * 60 * 60));
+	assert_eq!(parse_duration("7y"), None);
+	assert_eq!(parse_duration("d"), None);
+}
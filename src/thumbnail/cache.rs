@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write as _;
+use std::os::linux::fs::MetadataExt as _;
+use std::os::unix::fs::OpenOptionsExt as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{io_ctx, GenerateError};
+use crate::config::{Config, ThumbnailCache};
+
+/// The size of thumbnails in the `normal` directory of the freedesktop cache.
+const NORMAL_SIZE: u32 = 128;
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// Where the thumbnail of a file is cached.
+#[derive(Debug)]
+pub(super) struct Location {
+	/// Where the thumbnail is generated.
+	pub path: Arc<Path>,
+	/// The size that the thumbnail is generated at.
+	pub size: u32,
+	/// The URI of the file, which is embedded in the thumbnail in the freedesktop cache.
+	uri: Option<String>,
+	/// Thumbnails generated by other programs, which are used if they are fresh.
+	others: Vec<PathBuf>,
+	/// Locked while the thumbnail is being generated.
+	lock_path: PathBuf,
+	/// Where failures to generate the thumbnail are recorded.
+	fail_path: PathBuf,
+}
+
+/// A failure to generate a thumbnail, which is recorded so it is not retried on every request.
+#[derive(Debug)]
+pub struct Failure {
+	pub reason: String,
+	/// As a Unix timestamp.
+	pub failed_at: i64,
+}
+
+const REASON_KEY: &str = "X-dexr::Reason";
+const FAILED_AT_KEY: &str = "X-dexr::FailedAt";
+const PLUGINS_KEY: &str = "X-dexr::Plugins";
+
+impl Location {
+	pub(super) async fn new(
+		config: &Config,
+		fs_path: &Path,
+		relative_path: &Path,
+	) -> Result<Self, GenerateError> {
+		match config.thumbnail_cache {
+			ThumbnailCache::Private => {
+				let locks = config.thumbnail_tmp.join("locks");
+				let fail = config.thumbnail_tmp.join("fail");
+				for dir in [&locks, &fail] {
+					tokio::fs::create_dir_all(dir)
+						.await
+						.map_err(io_ctx("ensuring existence of thumbnail directory"))?;
+				}
+				let encoded_path = crate::util::encode_relative_path(relative_path);
+				Ok(Self {
+					path: config
+						.thumbnail_tmp
+						.join(format!("{encoded_path}.png"))
+						.into(),
+					size: super::SIZE,
+					uri: None,
+					others: Vec::new(),
+					lock_path: locks.join(format!("{encoded_path}.lock")),
+					fail_path: fail.join(format!("{encoded_path}.png")),
+				})
+			}
+			ThumbnailCache::Freedesktop => {
+				let uri = gstreamer::glib::filename_to_uri(fs_path, None)
+					.map_err(|_| GenerateError::Custom("could not convert path to URI"))?
+					.to_string();
+				let hash = format!("{:x}", md5::compute(&uri));
+				let normal = config.thumbnail_tmp.join("normal");
+				// kept out of the directories defined by the standard
+				let locks = config
+					.thumbnail_tmp
+					.join(concat!(env!("CARGO_PKG_NAME"), "-locks"));
+				let fail = config.thumbnail_tmp.join("fail").join(concat!(
+					env!("CARGO_PKG_NAME"),
+					"-",
+					env!("CARGO_PKG_VERSION")
+				));
+				for dir in [&normal, &locks, &fail] {
+					tokio::fs::DirBuilder::new()
+						.recursive(true)
+						.mode(0o700)
+						.create(dir)
+						.await
+						.map_err(io_ctx("ensuring existence of thumbnail directory"))?;
+				}
+				Ok(Self {
+					path: normal.join(format!("{hash}.png")).into(),
+					size: NORMAL_SIZE,
+					uri: Some(uri),
+					others: vec![config
+						.thumbnail_tmp
+						.join("large")
+						.join(format!("{hash}.png"))],
+					lock_path: locks.join(format!("{hash}.lock")),
+					fail_path: fail.join(format!("{hash}.png")),
+				})
+			}
+		}
+	}
+
+	/// Whether the cache is shared with other programs, in which case placeholders must not be written to it.
+	pub(super) fn is_shared(&self) -> bool {
+		self.uri.is_some()
+	}
+
+	/// Find a cached thumbnail that is up to date with the file, which was last modified at `source_mtime`.
+	pub(super) async fn find_fresh(&self, source_mtime: i64) -> std::io::Result<Option<PathBuf>> {
+		if !self.is_shared() {
+			// our own thumbnails are fresh if they were written after the file was modified
+			return match tokio::fs::metadata(&self.path).await {
+				Ok(metadata) if source_mtime < metadata.st_mtime() => Ok(Some(self.path.to_path_buf())),
+				Ok(_) => Ok(None),
+				Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+				Err(error) => Err(error),
+			};
+		}
+
+		for path in std::iter::once(&*self.path).chain(self.others.iter().map(PathBuf::as_path)) {
+			let contents = match tokio::fs::read(path).await {
+				Ok(contents) => contents,
+				Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+				Err(error) => return Err(error),
+			};
+			if self.describes(&text_chunks(&contents), source_mtime) {
+				return Ok(Some(path.to_owned()));
+			}
+		}
+
+		Ok(None)
+	}
+
+	/// Take an advisory lock on the thumbnail, waiting until other requests and processes sharing the cache release it.
+	/// The lock is released when the returned file is dropped.
+	pub(super) async fn lock(&self) -> std::io::Result<File> {
+		let lock_path = self.lock_path.clone();
+		let lock = tokio::task::spawn_blocking(move || {
+			let file = OpenOptions::new()
+				.write(true)
+				.create(true)
+				.truncate(false)
+				.open(lock_path)?;
+			file.lock()?;
+			Ok(file)
+		})
+		.await;
+		match lock {
+			Ok(lock) => lock,
+			Err(error) => std::panic::resume_unwind(error.into_panic()), /* assume that the task was not cancelled. */
+		}
+	}
+
+	/// Find a recorded failure that still applies.
+	/// A failure is retried if the file has changed, the gstreamer plugins have changed, or it is older than `retry_after`.
+	pub(super) async fn find_failure(
+		&self,
+		source_mtime: i64,
+		retry_after: Duration,
+	) -> std::io::Result<Option<Failure>> {
+		let contents = match tokio::fs::read(&self.fail_path).await {
+			Ok(contents) => contents,
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			Err(error) => return Err(error),
+		};
+		let mut texts = text_chunks(&contents);
+
+		if !self.describes(&texts, source_mtime)
+			|| texts.get(PLUGINS_KEY).map(String::as_str) != Some(super::generate::plugins_fingerprint())
+		{
+			return Ok(None);
+		}
+		let Some(failed_at) = texts
+			.get(FAILED_AT_KEY)
+			.and_then(|failed_at| failed_at.parse::<i64>().ok())
+		else {
+			return Ok(None);
+		};
+		let age = now().saturating_sub(failed_at);
+		if u64::try_from(age).is_ok_and(|age| age >= retry_after.as_secs()) {
+			return Ok(None);
+		}
+
+		Ok(Some(Failure {
+			reason: texts.remove(REASON_KEY).unwrap_or_default(),
+			failed_at,
+		}))
+	}
+
+	/// Write a generated thumbnail of the file, which was last modified at `source_mtime`, and forget any earlier failure.
+	pub(super) fn write(&self, png: &[u8], source_mtime: i64) -> std::io::Result<()> {
+		if self.is_shared() {
+			let mut texts = self.identifying_texts(source_mtime);
+			texts.push(("Software", env!("CARGO_PKG_NAME").to_owned()));
+			write_atomically(&self.path, &add_text_chunks(png, &texts), true)?;
+		} else {
+			write_atomically(&self.path, png, false)?;
+		}
+
+		match std::fs::remove_file(&self.fail_path) {
+			Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+			_ => Ok(()),
+		}
+	}
+
+	/// Record a failure to generate a thumbnail of the file, which was last modified at `source_mtime`.
+	///
+	/// Like in the freedesktop cache, the record is a placeholder image with the details in its `tEXt` chunks.
+	pub(super) fn write_failure(&self, reason: &str, source_mtime: i64) -> std::io::Result<Failure> {
+		let failed_at = now();
+		let mut texts = self.identifying_texts(source_mtime);
+		texts.extend([
+			(REASON_KEY, reason.to_owned()),
+			(FAILED_AT_KEY, failed_at.to_string()),
+			(
+				PLUGINS_KEY,
+				super::generate::plugins_fingerprint().to_owned(),
+			),
+		]);
+		write_atomically(
+			&self.fail_path,
+			&add_text_chunks(super::PLACEHOLDER, &texts),
+			self.is_shared(),
+		)?;
+
+		Ok(Failure {
+			reason: reason.to_owned(),
+			failed_at,
+		})
+	}
+
+	/// The `tEXt` chunks that tie a cached image to the file it was generated from.
+	fn identifying_texts(&self, source_mtime: i64) -> Vec<(&'static str, String)> {
+		let mut texts = vec![("Thumb::MTime", source_mtime.to_string())];
+		if let Some(uri) = &self.uri {
+			texts.push(("Thumb::URI", uri.clone()));
+		}
+		texts
+	}
+
+	fn describes(&self, texts: &HashMap<String, String>, source_mtime: i64) -> bool {
+		self
+			.uri
+			.as_ref()
+			.is_none_or(|uri| texts.get("Thumb::URI") == Some(uri))
+			&& texts
+				.get("Thumb::MTime")
+				.and_then(|mtime| mtime.parse::<i64>().ok())
+				== Some(source_mtime)
+	}
+}
+
+/// Move the cached thumbnails of `from` and everything in it to `to`, or remove them if it is `None`, after the files were moved or deleted.
+/// Both paths are relative to `index_root`.
+///
+/// Only the private cache is updated, since the freedesktop cache is keyed by hashes and its thumbnails are checked against the URI of the file anyway.
+pub async fn relocate(config: &Config, from: &Path, to: Option<&Path>) -> std::io::Result<()> {
+	if !matches!(config.thumbnail_cache, ThumbnailCache::Private) {
+		return Ok(());
+	}
+
+	for dir in [
+		config.thumbnail_tmp.clone(),
+		config.thumbnail_tmp.join("fail"),
+	] {
+		let mut entries = match tokio::fs::read_dir(&dir).await {
+			Ok(entries) => entries,
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+			Err(error) => return Err(error),
+		};
+		while let Some(entry) = entries.next_entry().await? {
+			let name = entry.file_name();
+			let Some(relative_path) = name
+				.to_str()
+				.and_then(|name| name.strip_suffix(".png"))
+				.and_then(crate::util::decode_relative_path)
+			else {
+				continue;
+			};
+			let Ok(rest) = relative_path.strip_prefix(from) else {
+				continue;
+			};
+
+			if let Some(to) = to {
+				let encoded = crate::util::encode_relative_path(&to.join(rest));
+				tokio::fs::rename(entry.path(), dir.join(format!("{encoded}.png"))).await?;
+			} else {
+				tokio::fs::remove_file(entry.path()).await?;
+			}
+		}
+	}
+
+	Ok(())
+}
+
+fn now() -> i64 {
+	time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// Write to a temporary file that is renamed into place, so readers never see a partially written file.
+fn write_atomically(path: &Path, contents: &[u8], owner_only: bool) -> std::io::Result<()> {
+	let mut options = OpenOptions::new();
+	// created exclusively, so that concurrent writes of the same file never share a temporary file
+	options.write(true).create_new(true);
+	if owner_only {
+		// the freedesktop standard requires that thumbnails are only readable by the owner
+		options.mode(0o600);
+	}
+
+	let (temp_path, mut file) = loop {
+		let mut temp_name = path.file_name().unwrap().to_owned();
+		temp_name.push(format!(
+			".{}.{:016x}.tmp",
+			std::process::id(),
+			fastrand::u64(..)
+		));
+		let temp_path = path.with_file_name(temp_name);
+		match options.open(&temp_path) {
+			Ok(file) => break (temp_path, file),
+			Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {}
+			Err(error) => return Err(error),
+		}
+	};
+
+	let result = file
+		.write_all(contents)
+		.and_then(|()| std::fs::rename(&temp_path, path));
+	if result.is_err() {
+		let _ = std::fs::remove_file(&temp_path);
+	}
+	result
+}
+
+/// Iterate over the type and data of the chunks in a PNG file, stopping at the first malformed chunk.
+fn chunks(png: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+	let mut rest = png.strip_prefix(PNG_SIGNATURE).unwrap_or_default();
+	std::iter::from_fn(move || {
+		let length = u32::from_be_bytes(rest.get(..4)?.try_into().unwrap());
+		let length = usize::try_from(length).ok()?;
+		let ty = rest.get(4..8)?;
+		let data = rest.get(8..8 + length)?;
+		// skip the CRC
+		rest = rest.get(8 + length + 4..)?;
+		Some((ty, data))
+	})
+}
+
+/// Read the `tEXt` chunks that come before the image data.
+fn text_chunks(png: &[u8]) -> HashMap<String, String> {
+	chunks(png)
+		.take_while(|&(ty, _)| ty != b"IDAT")
+		.filter(|&(ty, _)| ty == b"tEXt")
+		.filter_map(|(_, data)| {
+			let separator = data.iter().position(|&byte| byte == 0)?;
+			Some((
+				String::from_utf8_lossy(&data[..separator]).into_owned(),
+				String::from_utf8_lossy(&data[separator + 1..]).into_owned(),
+			))
+		})
+		.collect()
+}
+
+/// Insert `tEXt` chunks right after the `IHDR` chunk, which always comes first.
+fn add_text_chunks(png: &[u8], texts: &[(&str, String)]) -> Vec<u8> {
+	// the signature, then the length, type, data (always 13 bytes), and CRC of the `IHDR` chunk
+	const HEADER_END: usize = PNG_SIGNATURE.len() + 4 + 4 + 13 + 4;
+
+	let mut ret = Vec::with_capacity(png.len() + texts.len() * 64);
+	ret.extend_from_slice(&png[..HEADER_END]);
+	for (keyword, text) in texts {
+		write_chunk(
+			&mut ret,
+			*b"tEXt",
+			&[keyword.as_bytes(), b"\0", text.as_bytes()].concat(),
+		);
+	}
+	ret.extend_from_slice(&png[HEADER_END..]);
+	ret
+}
+
+fn write_chunk(out: &mut Vec<u8>, ty: [u8; 4], data: &[u8]) {
+	let mut crc = crc32fast::Hasher::new();
+	crc.update(&ty);
+	crc.update(data);
+
+	out.extend_from_slice(&u32::try_from(data.len()).unwrap().to_be_bytes());
+	out.extend_from_slice(&ty);
+	out.extend_from_slice(data);
+	out.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+#[test]
+fn test_text_chunks() {
+	let mut png = PNG_SIGNATURE.to_vec();
+	write_chunk(&mut png, *b"IHDR", &[0; 13]);
+	write_chunk(&mut png, *b"IDAT", &[]);
+	write_chunk(&mut png, *b"IEND", &[]);
+
+	let png = add_text_chunks(
+		&png,
+		&[
+			("Thumb::URI", "file:///a%20b.png".to_owned()),
+			("Thumb::MTime", "42".to_owned()),
+		],
+	);
+	let types: Vec<_> = chunks(&png).map(|(ty, _)| ty).collect();
+	assert_eq!(types, [b"IHDR", b"tEXt", b"tEXt", b"IDAT", b"IEND"]);
+
+	let texts = text_chunks(&png);
+	assert_eq!(texts["Thumb::URI"], "file:///a%20b.png");
+	assert_eq!(texts["Thumb::MTime"], "42");
+	assert_eq!(texts.len(), 2);
+}
@@ -0,0 +1,249 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::os::unix::fs::{OpenOptionsExt as _, PermissionsExt as _};
+use std::os::unix::process::CommandExt as _;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::sys::signal::{killpg, Signal};
+use nix::sys::wait::{waitid, Id, WaitPidFlag};
+use nix::unistd::Pid;
+use once_cell::sync::Lazy;
+
+use super::super::{io_ctx, GenerateError};
+use crate::config::Thumbnailer;
+
+const PDF_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An external program that renders a file into an image, such as poppler's `pdftoppm`.
+///
+/// In the arguments, `%i` is replaced with the input path, `%o` with the output path, and `%s` with the thumbnail size.
+/// If there is no `%o`, the image is read from the program's standard output.
+#[derive(Debug)]
+pub(in crate::thumbnail) struct Renderer {
+	program: PathBuf,
+	args: Vec<String>,
+	timeout: Duration,
+}
+
+static PDF_RENDERER: Lazy<Option<Renderer>> = Lazy::new(|| {
+	let program = find_program("pdftoppm")?;
+	let args = [
+		"-png",
+		"-singlefile",
+		"-f",
+		"1",
+		"-l",
+		"1",
+		"-scale-to",
+		"%s",
+		"%i",
+	];
+	Some(Renderer {
+		program,
+		args: args.map(str::to_owned).into(),
+		timeout: PDF_TIMEOUT,
+	})
+});
+
+/// Look for the external renderers so their availability is logged at startup rather than on first use.
+pub(in crate::thumbnail) fn discover() {
+	if let Some(renderer) = &*PDF_RENDERER {
+		tracing::info!(program = ?renderer.program, "found PDF renderer");
+	} else {
+		tracing::warn!(
+			"no PDF renderer found; install poppler's `pdftoppm` to generate thumbnails for PDFs"
+		);
+	}
+}
+
+pub(super) fn pdf_renderer() -> Result<&'static Renderer, GenerateError> {
+	PDF_RENDERER
+		.as_ref()
+		.ok_or(GenerateError::Custom("no PDF renderer available"))
+}
+
+fn find_program(name: &str) -> Option<PathBuf> {
+	let path = std::env::var_os("PATH")?;
+	std::env::split_paths(&path)
+		.map(|dir| dir.join(name))
+		.find(|candidate| {
+			candidate
+				.metadata()
+				.is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+		})
+}
+
+/// A rendered image in a temporary file, which is removed when this is dropped.
+#[derive(Debug)]
+pub(super) struct Rendered(PathBuf);
+
+impl Rendered {
+	/// A new empty file with a random name in the `render` directory of `thumbnail_tmp`.
+	/// It is created exclusively, so an existing file or symlink with the same name is never written through.
+	fn create(tmp: &Path) -> std::io::Result<(Self, File)> {
+		let dir = tmp.join("render");
+		std::fs::create_dir_all(&dir)?;
+		loop {
+			let path = dir.join(format!("{:016x}.png", fastrand::u64(..)));
+			match std::fs::OpenOptions::new()
+				.write(true)
+				.create_new(true)
+				.mode(0o600)
+				.open(&path)
+			{
+				Ok(file) => return Ok((Self(path), file)),
+				Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {}
+				Err(error) => return Err(error),
+			}
+		}
+	}
+
+	pub(super) fn path(&self) -> &Path {
+		&self.0
+	}
+}
+
+impl Drop for Rendered {
+	fn drop(&mut self) {
+		match std::fs::remove_file(&self.0) {
+			// the program may have failed before writing anything
+			Ok(()) => {}
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+			Err(error) => tracing::warn!(path = ?self.0, "could not remove rendered image: {error}"),
+		}
+	}
+}
+
+impl Renderer {
+	pub(super) fn configured(thumbnailer: &Thumbnailer) -> Result<Self, GenerateError> {
+		let (program, args) = thumbnailer
+			.command
+			.split_first()
+			.ok_or(GenerateError::Custom("thumbnailer command is empty"))?;
+		Ok(Self {
+			program: program.into(),
+			args: args.to_owned(),
+			timeout: Duration::from_secs(thumbnailer.timeout),
+		})
+	}
+
+	/// `tmp` is `thumbnail_tmp`, which the image is rendered into.
+	#[tracing::instrument(level = "debug")]
+	pub(super) fn render(
+		&self,
+		input: &Path,
+		size: u32,
+		tmp: &Path,
+	) -> Result<Rendered, GenerateError> {
+		let (rendered, file) = Rendered::create(tmp).map_err(io_ctx("creating rendered image"))?;
+		let writes_output = self.args.iter().any(|arg| arg.contains("%o"));
+		let stdout = if writes_output {
+			Stdio::null()
+		} else {
+			file.into()
+		};
+
+		let mut child = Command::new(&self.program)
+			.args(
+				self
+					.args
+					.iter()
+					.map(|arg| expand(arg, input, rendered.path(), size)),
+			)
+			.stdin(Stdio::null())
+			.stdout(stdout)
+			.stderr(Stdio::null())
+			// so that everything it starts can be killed along with it
+			.process_group(0)
+			.spawn()
+			.map_err(io_ctx("spawning external renderer"))?;
+
+		let status = self.wait(&mut child, input)?;
+		if status.success() {
+			Ok(rendered)
+		} else {
+			tracing::debug!(program = ?self.program, ?input, %status, "external renderer failed");
+			Err(GenerateError::Custom("external renderer failed"))
+		}
+	}
+
+	/// Wait for the program to exit, killing it along with anything it started once it takes longer than the timeout.
+	fn wait(&self, child: &mut Child, input: &Path) -> Result<ExitStatus, GenerateError> {
+		let pid = Pid::from_raw(i32::try_from(child.id()).unwrap());
+		// set once the program has exited, but before it is reaped, so that its ID cannot have been reused while the watchdog holds the lock to kill it
+		let exited = (Mutex::new(false), Condvar::new());
+
+		let timed_out = std::thread::scope(|scope| {
+			let watchdog = scope.spawn(|| {
+				let (lock, condvar) = &exited;
+				let (_exited, wait) = condvar
+					.wait_timeout_while(lock.lock().unwrap(), self.timeout, |exited| !*exited)
+					.unwrap();
+				if wait.timed_out() {
+					kill_group(pid);
+				}
+				wait.timed_out()
+			});
+
+			let waited = loop {
+				match waitid(Id::Pid(pid), WaitPidFlag::WEXITED | WaitPidFlag::WNOWAIT) {
+					Err(Errno::EINTR) => {}
+					result => break result,
+				}
+			};
+			let mut exited_guard = exited.0.lock().unwrap();
+			if let Err(error) = waited {
+				// so that reaping it below does not block forever
+				tracing::warn!("could not wait for external renderer: {error}");
+				kill_group(pid);
+			}
+			*exited_guard = true;
+			drop(exited_guard);
+			exited.1.notify_one();
+			watchdog.join().unwrap()
+		});
+
+		let status = child
+			.wait()
+			.map_err(io_ctx("waiting for external renderer"))?;
+		if timed_out {
+			tracing::warn!(program = ?self.program, ?input, "external renderer timed out");
+			return Err(GenerateError::Custom("external renderer timed out"));
+		}
+		Ok(status)
+	}
+}
+
+/// Kill the program along with anything it started, like the programs run by a shell script.
+/// The group has the same ID as the program, since it was spawned as the leader of a new group.
+fn kill_group(pid: Pid) {
+	if let Err(error) = killpg(pid, Signal::SIGKILL) {
+		tracing::warn!("could not kill external renderer: {error}");
+	}
+}
+
+fn expand(arg: &str, input: &Path, output: &Path, size: u32) -> OsString {
+	let mut ret = OsString::new();
+	let mut chars = arg.chars();
+	while let Some(ch) = chars.next() {
+		if ch != '%' {
+			ret.push(ch.encode_utf8(&mut [0; 4]));
+			continue;
+		}
+		match chars.next() {
+			Some('i') => ret.push(input),
+			Some('o') => ret.push(output),
+			Some('s') => ret.push(size.to_string()),
+			Some('%') | None => ret.push("%"),
+			Some(other) => {
+				ret.push("%");
+				ret.push(other.encode_utf8(&mut [0; 4]));
+			}
+		}
+	}
+	ret
+}
@@ -1,5 +1,5 @@
 use std::fs::File;
-use std::io::Write as _;
+use std::io::Read as _;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -7,17 +7,49 @@ use gst::prelude::{Cast as _, ElementExt as _, GstBinExt as _, ObjectExt as _};
 use once_cell::sync::OnceCell;
 use {gstreamer as gst, gstreamer_app as gst_app};
 
-use super::{io_ctx, GenerateError};
+use super::{io_ctx, GenerateError, Type};
+use crate::config::Thumbnailer;
 
 static GST_INIT: OnceCell<()> = OnceCell::new();
 
+mod external;
 mod scale_plugin;
 
+pub(in crate::thumbnail) use external::discover as discover_renderers;
+
 fn initialize_gst() {
 	gst::init().unwrap();
 	scale_plugin::plugin_register_static().unwrap();
 }
 
+pub(in crate::thumbnail) fn ensure_gst_initialized() {
+	GST_INIT.get_or_init(initialize_gst);
+}
+
+/// Identifies the installed gstreamer plugins, so failures can be retried when they change.
+/// Computed at startup by `discover_renderers`, since the first call blocks.
+pub(in crate::thumbnail) fn plugins_fingerprint() -> &'static str {
+	static FINGERPRINT: OnceCell<String> = OnceCell::new();
+
+	FINGERPRINT.get_or_init(|| {
+		ensure_gst_initialized();
+		let mut plugins: Vec<String> = gst::Registry::get()
+			.plugins()
+			.iter()
+			.map(|plugin| {
+				format!(
+					"{} {} {:?}",
+					plugin.plugin_name(),
+					plugin.version(),
+					plugin.filename()
+				)
+			})
+			.collect();
+		plugins.sort_unstable();
+		format!("{:x}", md5::compute(plugins.join("\n")))
+	})
+}
+
 struct PipelineWrapper(pub gst::Pipeline);
 
 impl std::ops::Deref for PipelineWrapper {
@@ -40,14 +72,40 @@ impl Drop for PipelineWrapper {
 	}
 }
 
+/// Generate a PNG thumbnail that fits within a square of `size` pixels.
 #[tracing::instrument]
-pub(in crate::thumbnail) fn generate(input: &Path, mut output: &File) -> Result<(), GenerateError> {
-	GST_INIT.get_or_init(initialize_gst);
+pub(in crate::thumbnail) fn generate(
+	input: &Path,
+	ty: Type,
+	size: u32,
+	thumbnailers: &[Thumbnailer],
+	tmp: &Path,
+) -> Result<Vec<u8>, GenerateError> {
+	ensure_gst_initialized();
 
 	let frame = Arc::new(atomic_refcell::AtomicRefCell::new(None));
 
+	// text is fed to the pipeline directly rather than read by it, and documents and external types are first rendered to an image by an external program
+	let mut input = match ty {
+		Type::Image | Type::Video => Input::File(input),
+		Type::Text => Input::Text(first_lines(input).map_err(io_ctx("reading text"))?),
+		Type::Document => Input::Rendered(external::pdf_renderer()?.render(input, size, tmp)?),
+		Type::External => {
+			let thumbnailer = input
+				.extension()
+				.and_then(std::ffi::OsStr::to_str)
+				.and_then(|extension| super::find_thumbnailer(extension, thumbnailers))
+				.ok_or(GenerateError::NotRich)?;
+			Input::Rendered(external::Renderer::configured(thumbnailer)?.render(input, size, tmp)?)
+		}
+	};
+
 	// wrapper will handle setting the pipeline state to Null
-	let pipeline = PipelineWrapper(create_pipeline(input));
+	let pipeline = PipelineWrapper(match &input {
+		Input::File(path) => create_pipeline(path, size),
+		Input::Rendered(rendered) => create_pipeline(rendered.path(), size),
+		Input::Text(_) => create_text_pipeline(size),
+	});
 
 	let sink = pipeline
 		.by_name("sink")
@@ -82,6 +140,20 @@ pub(in crate::thumbnail) fn generate(input: &Path, mut output: &File) -> Result<
 		.set_state(gst::State::Playing)
 		.map_err(|_| GenerateError::Custom("pipeline failed"))?;
 
+	if let Input::Text(text) = &mut input {
+		let src = pipeline
+			.by_name("src")
+			.unwrap()
+			.downcast::<gst_app::AppSrc>()
+			.unwrap();
+		src
+			.push_buffer(gst::Buffer::from_slice(std::mem::take(text).into_bytes()))
+			.map_err(|_| GenerateError::Custom("pushing text into pipeline failed"))?;
+		src
+			.end_of_stream()
+			.map_err(|_| GenerateError::Custom("ending text stream failed"))?;
+	}
+
 	let bus = pipeline.bus().unwrap();
 	// timed_pop with None for the time blocks until there's a message
 	tracing::trace!("starting gstreamer bus message read loop");
@@ -124,19 +196,79 @@ pub(in crate::thumbnail) fn generate(input: &Path, mut output: &File) -> Result<
 	}
 
 	tracing::trace!("receiving frame from pipeline");
-	let frame = frame
-		.borrow_mut()
-		.take()
-		.ok_or(GenerateError::Custom("video has no frames"))?;
-
-	tracing::trace!("writing frame to output");
-	output.write_all(&frame).map_err(io_ctx("writing output"))?;
-	Ok(())
+	let frame = frame.borrow_mut().take();
+	frame.ok_or(GenerateError::Custom("video has no frames"))
+}
+
+/// What is fed to the pipeline.
+/// A rendered image must outlive the pipeline, since its temporary file is removed when it is dropped.
+enum Input<'a> {
+	File(&'a Path),
+	Text(String),
+	Rendered(external::Rendered),
+}
+
+/// Read the start of a text file to render into its thumbnail.
+fn first_lines(input: &Path) -> std::io::Result<String> {
+	const MAX_BYTES: u64 = 4096;
+	const MAX_LINES: usize = 16;
+	const MAX_LINE_LENGTH: usize = 48;
+
+	let mut buffer = Vec::new();
+	File::open(input)?
+		.take(MAX_BYTES)
+		.read_to_end(&mut buffer)?;
+
+	let text = String::from_utf8_lossy(&buffer);
+	let lines: Vec<String> = text
+		.lines()
+		.take(MAX_LINES)
+		.map(|line| line.chars().take(MAX_LINE_LENGTH).collect())
+		.collect();
+	// textrender refuses to render empty buffers
+	Ok(if lines.iter().all(|line| line.trim().is_empty()) {
+		" ".to_owned()
+	} else {
+		lines.join("\n")
+	})
+}
+
+fn create_text_pipeline(size: u32) -> gst::Pipeline {
+	let size = format!("size={size}");
+	tracing::trace!("launching gstreamer text pipeline");
+	gst::parse_launchv(&[
+		"appsrc",
+		"name=src",
+		"caps=text/x-raw,format=utf8",
+		"!",
+		"textrender",
+		"font-desc=monospace 10",
+		"halignment=left",
+		"valignment=top",
+		"line-alignment=left",
+		"!",
+		"video/x-raw,width=256,height=256",
+		"!",
+		"videoconvert",
+		"!",
+		"thumbnailscale",
+		&size,
+		"!",
+		"pngenc",
+		"snapshot=false",
+		"!",
+		"appsink",
+		"name=sink",
+	])
+	.expect("invalid pipeline")
+	.downcast::<gst::Pipeline>()
+	.unwrap()
 }
 
-fn create_pipeline(input: &Path) -> gst::Pipeline {
+fn create_pipeline(input: &Path, size: u32) -> gst::Pipeline {
 	let input = input.to_string_lossy();
 	let location = format!("location={input}");
+	let size = format!("size={size}");
 	tracing::trace!("launching gstreamer pipeline");
 	gst::parse_launchv(&[
 		"filesrc",
@@ -149,6 +281,7 @@ fn create_pipeline(input: &Path) -> gst::Pipeline {
 		"videoconvert",
 		"!",
 		"thumbnailscale",
+		&size,
 		"!",
 		"pngenc",
 		"snapshot=false",
@@ -1,8 +1,11 @@
+use std::sync::atomic::{self, AtomicU32};
+
 use atomic_refcell::AtomicRefCell;
 use glib::subclass::InitializingObject;
 use gst::glib;
+use gst::glib::ParamSpecBuilderExt as _;
 use gst::prelude::{
-	Cast as _, GstBinExtManual as _, ObjectExt, PadExtManual as _, StaticType as _,
+	Cast as _, GstBinExtManual as _, ObjectExt, PadExtManual as _, StaticType as _, ToValue as _,
 };
 use gst::subclass::prelude::{
 	BinImpl, ElementImpl, GstObjectImpl, ObjectImpl, ObjectSubclass, ObjectSubclassIsExt as _,
@@ -27,11 +30,17 @@ struct Data {
 	capsfilter: gst::Element,
 }
 
-struct ScaleElementImpl(AtomicRefCell<Option<Data>>);
+struct ScaleElementImpl {
+	data: AtomicRefCell<Option<Data>>,
+	size: AtomicU32,
+}
 
 impl Default for ScaleElementImpl {
 	fn default() -> Self {
-		Self(AtomicRefCell::new(None))
+		Self {
+			data: AtomicRefCell::new(None),
+			size: AtomicU32::new(crate::thumbnail::SIZE),
+		}
 	}
 }
 
@@ -69,10 +78,14 @@ fn sink_event_handler(
 	if let gst::EventView::Caps(caps) = event.view() {
 		let info = gst_video::VideoInfo::from_caps(caps.caps()).unwrap();
 
-		let (width, height) =
-			calculate_actual_size(info.width(), info.height(), crate::thumbnail::SIZE);
+		let imp = filter.imp();
+		let (width, height) = calculate_actual_size(
+			info.width(),
+			info.height(),
+			imp.size.load(atomic::Ordering::Relaxed),
+		);
 
-		let imp = filter.imp().0.borrow();
+		let imp = imp.data.borrow();
 		let inner = imp.as_ref().unwrap();
 		let mut caps: gst::Caps = inner.capsfilter.property("caps");
 		caps.make_mut().set_simple(&[
@@ -123,14 +136,43 @@ impl ObjectSubclass for ScaleElementImpl {
 				.unwrap();
 		obj.add_pad(&src_pad).unwrap();
 
-		let old = obj.imp().0.borrow_mut().replace(Data {
+		let old = obj.imp().data.borrow_mut().replace(Data {
 			capsfilter: specify_size,
 		});
 		assert!(old.is_none(), "`constructed` called multiple times");
 	}
 }
 
-impl ObjectImpl for ScaleElementImpl {}
+impl ObjectImpl for ScaleElementImpl {
+	fn properties() -> &'static [glib::ParamSpec] {
+		static PROPERTIES: Lazy<[glib::ParamSpec; 1]> = Lazy::new(|| {
+			[glib::ParamSpecUInt::builder("size")
+				.nick("Size")
+				.blurb("The size of the square that the image is scaled to fit within")
+				.minimum(1)
+				.default_value(crate::thumbnail::SIZE)
+				.build()]
+		});
+
+		&*PROPERTIES
+	}
+
+	fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+		match pspec.name() {
+			"size" => self
+				.size
+				.store(value.get().unwrap(), atomic::Ordering::Relaxed),
+			_ => unimplemented!(),
+		}
+	}
+
+	fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+		match pspec.name() {
+			"size" => self.size.load(atomic::Ordering::Relaxed).to_value(),
+			_ => unimplemented!(),
+		}
+	}
+}
 
 impl GstObjectImpl for ScaleElementImpl {}
 
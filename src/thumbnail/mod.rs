@@ -1,36 +1,86 @@
-use std::collections::HashMap;
-use std::io::{Seek, SeekFrom, Write};
 use std::os::linux::fs::MetadataExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::response::{IntoResponse, Response};
 use serde::Serialize;
-use tokio::sync::{watch, Mutex};
 
+use crate::config::{Config, Thumbnailer};
+
+mod cache;
 mod generate;
+mod probe;
+
+pub use cache::{relocate, Failure};
+pub use probe::{probe, MediaInfo};
 
 pub const SIZE: u32 = 48;
 
+/// Served in place of thumbnails that could not be generated.
+const PLACEHOLDER: &[u8] = include_bytes!("../../static/unknown.png");
+
+/// Look for the external programs used to render thumbnails, logging which are available.
+/// Also fingerprints the gstreamer plugins, which initializes gstreamer and scans its registry, so that this blocking work is done before serving rather than on the first request.
+pub fn discover_renderers() {
+	generate::discover_renderers();
+	generate::plugins_fingerprint();
+}
+
 #[derive(Debug, Clone, Copy, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Type {
 	Image,
 	Video,
+	/// Source code, logs, and other plain text, as well as Markdown.
+	Text,
+	/// PDFs, rendered by an external program.
+	Document,
+	/// Generated by one of the thumbnailers in the config.
+	External,
 }
 
 impl Type {
-	pub fn from_extension(extension: &str) -> Option<Self> {
+	/// Configured thumbnailers take precedence over the built-in types.
+	pub fn for_extension(extension: &str, thumbnailers: &[Thumbnailer]) -> Option<Self> {
+		if find_thumbnailer(extension, thumbnailers).is_some() {
+			Some(Self::External)
+		} else {
+			Self::from_extension(extension)
+		}
+	}
+
+	/// Whether the overlay can show a preview of files of this type.
+	pub fn has_preview(self) -> bool {
+		!matches!(self, Self::External)
+	}
+
+	fn from_extension(extension: &str) -> Option<Self> {
 		match extension {
 			"avif" | "jpg" | "jpeg" | "png" | "gif" | "webp" | "tif" | "tiff" | "tga" | "dds" | "bmp"
 			| "ico" | "hdr" | "exr" | "pbm" | "pam" | "ppm" | "pgm" | "ff" | "farbfeld" => Some(Self::Image),
 			"mkv" | "webm" | "mp4" | "3gp" | "mpeg" | "mp2" | "mpe" | "mpv" | "ogg" | "avi" | "m4p"
 			| "m4v" | "mov" => Some(Self::Video),
+			"txt" | "text" | "log" | "md" | "markdown" | "json" | "toml" | "yaml" | "yml" | "xml"
+			| "csv" | "tsv" | "ini" | "cfg" | "conf" | "rs" | "py" | "js" | "mjs" | "ts" | "c" | "h"
+			| "cc" | "cpp" | "hpp" | "go" | "java" | "kt" | "rb" | "lua" | "php" | "pl" | "sh"
+			| "bash" | "zsh" | "css" | "scss" | "sql" | "diff" | "patch" | "tex" | "hs" | "ml" | "cs"
+			| "swift" => Some(Self::Text),
+			"pdf" => Some(Self::Document),
 			_ => None,
 		}
 	}
 }
 
+fn find_thumbnailer<'a>(
+	extension: &str,
+	thumbnailers: &'a [Thumbnailer],
+) -> Option<&'a Thumbnailer> {
+	thumbnailers
+		.iter()
+		.find(|thumbnailer| thumbnailer.handles(extension))
+}
+
 #[derive(Debug)]
 pub enum GenerateError {
 	NotRich,
@@ -39,126 +89,187 @@ pub enum GenerateError {
 		error: std::io::Error,
 	},
 	Custom(&'static str),
+	/// Too many thumbnails were generated for the client recently, and it can try again after this long.
+	RateLimited(Duration),
 }
 
 impl IntoResponse for &GenerateError {
 	fn into_response(self) -> Response {
 		match self {
-			GenerateError::NotRich => (
+			GenerateError::NotRich => crate::error::response(
 				http::StatusCode::NOT_FOUND,
 				"file type does not support rich thumbnails",
-			)
-				.into_response(),
+			),
 			GenerateError::Io { context, error } => crate::error::Io { context, error }.into_response(),
-			&GenerateError::Custom(message) => (http::StatusCode::NOT_FOUND, message).into_response(),
+			&GenerateError::Custom(message) => {
+				crate::error::response(http::StatusCode::NOT_FOUND, message)
+			}
+			&GenerateError::RateLimited(retry_after) => {
+				crate::error::TooManyRequests(retry_after).into_response()
+			}
+		}
+	}
+}
+
+impl GenerateError {
+	fn reason(&self) -> String {
+		match self {
+			Self::NotRich => "file type does not support rich thumbnails".to_owned(),
+			Self::Io { context, error } => format!("{context}: {error}"),
+			Self::Custom(message) => (*message).to_owned(),
+			Self::RateLimited(_) => "rate limited".to_owned(),
 		}
 	}
 }
 
+/// Served with the placeholder image, the reason in the `X-Thumbnail-Error` header, and when it failed as a Unix timestamp in the `X-Thumbnail-Failed-At` header.
+impl IntoResponse for Failure {
+	fn into_response(self) -> Response {
+		let reason = http::HeaderValue::from_str(&self.reason)
+			.unwrap_or_else(|_| http::HeaderValue::from_static("unknown"));
+		(
+			[
+				(
+					http::header::CONTENT_TYPE,
+					http::HeaderValue::from_static("image/png"),
+				),
+				(
+					http::header::HeaderName::from_static("x-thumbnail-error"),
+					reason,
+				),
+				(
+					http::header::HeaderName::from_static("x-thumbnail-failed-at"),
+					http::HeaderValue::from(self.failed_at),
+				),
+			],
+			PLACEHOLDER,
+		)
+			.into_response()
+	}
+}
+
+#[derive(Debug)]
+pub enum Thumbnail {
+	/// The path to the generated thumbnail.
+	Generated(PathBuf),
+	/// Generation failed, either now or recently enough that it was not retried.
+	Failed(Failure),
+}
+
+/// Generate the thumbnail of a file if there is no fresh one in the cache.
+/// `permit` is only asked for if the thumbnail actually has to be generated, and returns how long to wait if it is not given.
 pub async fn generate(
-	state: Arc<State>,
+	config: Arc<Config>,
+	ty: Type,
 	fs_path: Arc<Path>,
-	thumbnail_path: Arc<Path>,
-) -> Result<(), Arc<GenerateError>> {
+	relative_path: &Path,
+	permit: impl FnOnce() -> Result<(), Duration> + Send,
+) -> Result<Thumbnail, GenerateError> {
+	let location = cache::Location::new(&config, &fs_path, relative_path).await?;
 	Generator {
-		state,
+		config,
+		ty,
 		fs_path,
-		thumbnail_path,
+		location,
 	}
-	.generate()
+	.generate(permit)
 	.await
 }
 
-type GenerateResult = Result<(), Arc<GenerateError>>;
-type ActiveReceiver = watch::Receiver<GenerateResult>;
-type ActiveMap = HashMap<Arc<Path>, ActiveReceiver>;
-
-#[derive(Default, Debug)]
-pub struct State {
-	active: Mutex<ActiveMap>,
-}
-
 #[derive(Debug)]
 struct Generator {
-	state: Arc<State>,
+	config: Arc<Config>,
+	ty: Type,
 	fs_path: Arc<Path>,
-	thumbnail_path: Arc<Path>,
+	location: cache::Location,
 }
 
 impl Generator {
-	#[tracing::instrument(level = "debug")]
-	async fn generate(self) -> GenerateResult {
-		if self
-			.is_fresh()
+	#[tracing::instrument(level = "debug", skip(permit))]
+	async fn generate(
+		self,
+		permit: impl FnOnce() -> Result<(), Duration> + Send,
+	) -> Result<Thumbnail, GenerateError> {
+		let source_mtime = tokio::fs::metadata(&self.fs_path)
+			.await
+			.map_err(io_ctx("reading metadata of source file"))?
+			.st_mtime();
+		if let Some(existing) = self.find_existing(source_mtime).await? {
+			return Ok(existing);
+		}
+		permit().map_err(GenerateError::RateLimited)?;
+
+		tracing::trace!("waiting for thumbnail lock");
+		let lock = self
+			.location
+			.lock()
+			.await
+			.map_err(io_ctx("locking thumbnail"))?;
+		// another request or process may have generated the thumbnail while we were waiting
+		if let Some(existing) = self.find_existing(source_mtime).await? {
+			return Ok(existing);
+		}
+
+		let thumbnail = tokio_rayon::spawn(move || {
+			tracing::trace!("inside spawned rayon task");
+			match generate::generate(
+				&self.fs_path,
+				self.ty,
+				self.location.size,
+				&self.config.thumbnailers,
+				&self.config.thumbnail_tmp,
+			) {
+				Ok(png) => {
+					self
+						.location
+						.write(&png, source_mtime)
+						.map_err(io_ctx("writing thumbnail"))?;
+					Ok(Thumbnail::Generated(self.location.path.to_path_buf()))
+				}
+				Err(error) => {
+					tracing::warn!(fs_path = ?self.fs_path, "thumbnail generation failed: {error:?}");
+					let failure = self
+						.location
+						.write_failure(&error.reason(), source_mtime)
+						.map_err(io_ctx("recording thumbnail failure"))?;
+					Ok(Thumbnail::Failed(failure))
+				}
+			}
+		})
+		.await;
+		drop(lock);
+
+		thumbnail
+	}
+
+	/// Find a fresh thumbnail or a failure that should not be retried yet.
+	async fn find_existing(&self, source_mtime: i64) -> Result<Option<Thumbnail>, GenerateError> {
+		if let Some(fresh) = self
+			.location
+			.find_fresh(source_mtime)
 			.await
 			.map_err(io_ctx("checking freshness of thumbnail"))?
 		{
-			tracing::trace!("thumbnail is fresh, not regenerating");
-			return Ok(());
+			tracing::trace!(?fresh, "thumbnail is fresh, not regenerating");
+			return Ok(Some(Thumbnail::Generated(fresh)));
 		}
 
-		tracing::trace!("locking active tracker");
-		let mut active = self.state.active.lock().await;
-		if let Some(result_channel) = active.get(&*self.thumbnail_path) {
-			let mut result_channel = result_channel.clone();
-			drop(active);
-			result_channel
-				.changed()
-				.await
-				.expect("thumbnail sender was dropped");
-			let borrowed = result_channel.borrow_and_update();
-			borrowed.clone()
-		} else {
-			let (active_send, active_recv) = watch::channel(Ok(()));
-			active.insert(Arc::clone(&self.thumbnail_path), active_recv);
-			drop(active);
-			let result = tokio_rayon::spawn({
-				tracing::trace!("inside spawned rayon task");
-				let thumbnail_path = self.thumbnail_path.clone();
-				move || {
-					use std::fs::File;
-					let mut output =
-						File::create(thumbnail_path).map_err(io_ctx("opening thumbnail output"))?;
-					let result = generate::generate(&self.fs_path, &output);
-					if result.is_err() {
-						tracing::trace!("thumbnail generation failed; overwriting output with placeholder");
-						output.set_len(0).map_err(io_ctx("truncating output"))?;
-						output
-							.seek(SeekFrom::Start(0))
-							.map_err(io_ctx("seeking to start of output"))?;
-						output
-							.write_all(include_bytes!("../../static/unknown.png"))
-							.map_err(io_ctx("writing placeholder image to output"))?;
-					}
-					result
-				}
-			})
-			.await;
-			let result = result.map_err(Arc::new);
-			let mut active = self.state.active.lock().await;
-			active
-				.remove(&self.thumbnail_path)
-				.expect("active entry missing for thumbnail we just generated");
-			drop(active);
-			let _ = active_send.send(result.clone());
-			result
+		let retry_after = Duration::from_secs(self.config.thumbnail_retry_after);
+		if let Some(failure) = self
+			.location
+			.find_failure(source_mtime, retry_after)
+			.await
+			.map_err(io_ctx("checking for earlier failure"))?
+		{
+			tracing::trace!(
+				?failure,
+				"thumbnail generation failed recently, not retrying"
+			);
+			return Ok(Some(Thumbnail::Failed(failure)));
 		}
-	}
 
-	async fn is_fresh(&self) -> std::io::Result<bool> {
-		let (input_mtime, output_mtime) = tokio::join!(
-			tokio::fs::metadata(&self.fs_path),
-			tokio::fs::metadata(&self.thumbnail_path)
-		);
-		let input_mtime = input_mtime?.st_mtime();
-		let output_mtime = match output_mtime {
-			Err(not_found) if not_found.kind() == std::io::ErrorKind::NotFound => return Ok(false),
-			other => other?.st_mtime(),
-		};
-		Ok(input_mtime < output_mtime)
+		Ok(None)
 	}
-
-	// `generate_image` and `generate_video` are implemented in the `image` and `video` modules respectively.
 }
 
 fn io_ctx(context: &'static str) -> impl FnOnce(std::io::Error) -> GenerateError {
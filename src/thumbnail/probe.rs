@@ -0,0 +1,122 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use gstreamer as gst;
+use gstreamer_pbutils as gst_pbutils;
+use serde::Serialize;
+
+use super::Type;
+
+const DISCOVERER_TIMEOUT: gst::ClockTime = gst::ClockTime::from_seconds(5);
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct MediaInfo {
+	/// When the media was captured, as a Unix timestamp.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub captured: Option<i64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub dimensions: Option<(u32, u32)>,
+	/// The duration of the media in milliseconds.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub duration: Option<u64>,
+}
+
+impl MediaInfo {
+	pub fn pixels(&self) -> Option<u64> {
+		self
+			.dimensions
+			.map(|(width, height)| u64::from(width) * u64::from(height))
+	}
+}
+
+/// Reads the capture date, dimensions, and duration of a media file.
+///
+/// This can be slow (especially for videos) and blocks, so it should be run on a worker thread.
+/// Information that cannot be determined is left as `None`.
+#[tracing::instrument(level = "debug")]
+pub fn probe(path: &Path, ty: Type) -> MediaInfo {
+	match ty {
+		Type::Image => probe_image(path),
+		Type::Video => probe_video(path).unwrap_or_else(|error| {
+			tracing::debug!("discovering video failed: {error}");
+			MediaInfo::default()
+		}),
+		Type::Text | Type::Document | Type::External => MediaInfo::default(),
+	}
+}
+
+fn probe_image(path: &Path) -> MediaInfo {
+	let dimensions = match imagesize::size(path) {
+		Ok(size) => u32::try_from(size.width)
+			.ok()
+			.zip(u32::try_from(size.height).ok()),
+		Err(error) => {
+			tracing::debug!("reading image size failed: {error}");
+			None
+		}
+	};
+
+	MediaInfo {
+		captured: exif_capture_date(path),
+		dimensions,
+		duration: None,
+	}
+}
+
+fn exif_capture_date(path: &Path) -> Option<i64> {
+	let file = File::open(path).ok()?;
+	let exif = exif::Reader::new()
+		.read_from_container(&mut BufReader::new(file))
+		.ok()?;
+	let field = [exif::Tag::DateTimeOriginal, exif::Tag::DateTime]
+		.into_iter()
+		.find_map(|tag| exif.get_field(tag, exif::In::PRIMARY))?;
+	let exif::Value::Ascii(values) = &field.value else {
+		return None;
+	};
+	let date_time = exif::DateTime::from_ascii(values.first()?).ok()?;
+
+	let date = time::Date::from_calendar_date(
+		date_time.year.into(),
+		time::Month::try_from(date_time.month).ok()?,
+		date_time.day,
+	)
+	.ok()?;
+	let time = time::Time::from_hms(date_time.hour, date_time.minute, date_time.second).ok()?;
+	let offset = time::UtcOffset::from_whole_seconds(
+		date_time
+			.offset
+			.map_or(0, |minutes| i32::from(minutes) * 60),
+	)
+	.ok()?;
+	Some(
+		time::PrimitiveDateTime::new(date, time)
+			.assume_offset(offset)
+			.unix_timestamp(),
+	)
+}
+
+fn probe_video(path: &Path) -> Result<MediaInfo, gst::glib::Error> {
+	super::generate::ensure_gst_initialized();
+
+	let uri = gst::glib::filename_to_uri(path, None)?;
+	let info = gst_pbutils::Discoverer::new(DISCOVERER_TIMEOUT)?.discover_uri(&uri)?;
+
+	let captured = info
+		.tags()
+		.and_then(|tags| tags.get::<gst::tags::DateTime>())
+		.and_then(|date_time| date_time.get().to_g_date_time().ok())
+		.map(|date_time| date_time.to_unix());
+	let dimensions = info
+		.video_streams()
+		.first()
+		.map(|stream| (stream.width(), stream.height()));
+	let duration = info.duration().map(gst::ClockTime::mseconds);
+
+	Ok(MediaInfo {
+		captured,
+		dimensions,
+		duration,
+	})
+}
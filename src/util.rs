@@ -1,4 +1,13 @@
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Everything except unreserved characters, so that names are always encoded the same way.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+	.remove(b'-')
+	.remove(b'.')
+	.remove(b'_')
+	.remove(b'~');
 
 pub fn join_paths<'a>(items: impl IntoIterator<Item = &'a str>) -> String {
 	let mut ret = "/".to_owned();
@@ -15,6 +24,33 @@ pub fn join_paths<'a>(items: impl IntoIterator<Item = &'a str>) -> String {
 	ret
 }
 
+/// Percent-encoded, starting with a slash, for URLs that must be ASCII, such as in `Location`.
+pub fn encode_path(path: &Path) -> String {
+	let mut ret = String::new();
+	for component in path.components() {
+		if let Component::Normal(name) = component {
+			ret.push('/');
+			ret.extend(utf8_percent_encode(&name.to_string_lossy(), SEGMENT));
+		}
+	}
+	if ret.is_empty() {
+		ret.push('/');
+	}
+	ret
+}
+
 pub fn encode_relative_path(path: &Path) -> String {
 	base64::encode_config(path.to_string_lossy().as_bytes(), base64::URL_SAFE)
 }
+
+pub fn decode_relative_path(encoded: &str) -> Option<PathBuf> {
+	let decoded = base64::decode_config(encoded, base64::URL_SAFE).ok()?;
+	String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+#[test]
+fn test_encode_path() {
+	assert_eq!(encode_path(Path::new("/")), "/");
+	assert_eq!(encode_path(Path::new("/a b/c#d.txt")), "/a%20b/c%23d.txt");
+	assert_eq!(encode_path(Path::new("/ä")), "/%C3%A4");
+}
@@ -1,10 +1,18 @@
 // @license magnet:?xt=urn:btih:0b31508aeb0634b347b8270c7bee4d411b5d4109&dn=agpl-3.0.txt AGPL-3.0-or-Later
 
+// the other static files are next to this one, which may be under a base path
+const static_base = document.currentScript.src;
+
+function static_url(name) {
+	return new URL(name, static_base).href;
+}
+
 // preview
 
 const entries = [...entriesList.children].map((entry) => {
 	let ret = JSON.parse(entry.dataset.entry);
 	ret.url = entry.dataset.entryUrl;
+	ret.preview_url = entry.dataset.entryPreviewUrl;
 	return ret;
 });
 
@@ -16,6 +24,10 @@ function preview_on_click(element, event) {
 	return false;
 }
 
+for (const link of entriesList.querySelectorAll("a.has-preview")) {
+	link.addEventListener("click", (event) => preview_on_click(link, event));
+}
+
 let preview_current_index = null;
 let preview_current = null;
 
@@ -31,32 +43,64 @@ function preview_open(idx) {
 		case "image":
 			item_element = document.createElement("img");
 			item_element.alt = preview_current.name;
+			item_element.src = preview_current.url;
 			break;
 		case "video":
 			item_element = document.createElement("video");
 			item_element.controls = true;
 			item_element.autoplay = true;
+			item_element.src = preview_current.url;
+			break;
+		case "text":
+			item_element = document.createElement("div");
+			item_element.className = "text-preview";
+			fetch(preview_current.preview_url)
+				.then((response) => response.text())
+				.then((html) => {
+					item_element.innerHTML = html;
+				});
+			break;
+		case "document":
+			item_element = document.createElement("iframe");
+			item_element.className = "document-preview";
+			item_element.title = preview_current.name;
+			item_element.src = preview_current.url;
 			break;
 	}
-	item_element.src = preview_current.url;
 	item_element.id = "previewItem";
 	previewItemContainer.appendChild(item_element);
 
 	const previous = entries[get_first_valid_index_at_or_before(preview_current_index - 1)];
 	if (previous) {
-		prefetchBefore.href = previous.url;
-		prefetchBefore.as = previous.thumbnail.value;
+		prefetch(prefetchBefore, previous);
 	}
 
 	const next = entries[get_first_valid_index_at_or_after(preview_current_index + 1)];
 	if (next) {
-		prefetchAfter.href = next.url;
-		prefetchAfter.as = next.thumbnail.value;
+		prefetch(prefetchAfter, next);
 	}
 
 	preview.classList.add("open");
 }
 
+function prefetch(link, entry) {
+	switch (entry.thumbnail.value) {
+		case "text":
+			link.href = entry.preview_url;
+			link.as = "fetch";
+			break;
+		case "image":
+		case "video":
+			link.href = entry.url;
+			link.as = entry.thumbnail.value;
+			break;
+		// documents are shown in a frame, which cannot be preloaded
+		default:
+			link.removeAttribute("href");
+			link.removeAttribute("as");
+	}
+}
+
 function preview_close() {
 	location.hash = "";
 	fullscreen_exit();
@@ -67,9 +111,13 @@ function preview_close() {
 	preview.classList.remove("open");
 }
 
+function has_preview(entry) {
+	return entry.thumbnail.type === "rich" && entry.thumbnail.value !== "external";
+}
+
 function get_first_valid_index_at_or_before(cur) {
 	for (let i = cur; i >= 0; --i) {
-		if (entries[i].thumbnail.value) {
+		if (has_preview(entries[i])) {
 			return i;
 		}
 	}
@@ -78,7 +126,7 @@ function get_first_valid_index_at_or_before(cur) {
 
 function get_first_valid_index_at_or_after(cur) {
 	for (let i = cur; i < entries.length; ++i) {
-		if (entries[i].thumbnail.value) {
+		if (has_preview(entries[i])) {
 			return i;
 		}
 	}
@@ -88,7 +136,7 @@ function get_first_valid_index_at_or_after(cur) {
 function preview_download() {
 	const downloader = document.createElement("a");
 	downloader.download = preview_current.name;
-	downloader.href = preview_current.url;
+	downloader.href = preview_current.url + "?download";
 	downloader.click();
 }
 
@@ -120,6 +168,12 @@ function preview_next() {
 	}
 }
 
+previewPrevious.addEventListener("click", () => preview_previous());
+previewNext.addEventListener("click", () => preview_next());
+previewNext.addEventListener("dblclick", () => slideshow_start());
+previewDownload.addEventListener("click", () => preview_download());
+previewClose.addEventListener("click", () => preview_close());
+
 if (location.hash) {
 	const file = decodeURIComponent(location.hash.slice(1));
 	const index = entries.findIndex((entry) => entry.name === file);
@@ -154,14 +208,16 @@ function fullscreen_toggle() {
 
 document.addEventListener("fullscreenchange", () => {
 	if (document.fullscreenElement) {
-		previewFullscreenButton.src = "/static/fullscreen-exit.png";
+		previewFullscreenButton.src = static_url("fullscreen-exit.png");
 		previewFullscreenButton.alt = "Exit fullscreen";
 	} else {
-		previewFullscreenButton.src = "/static/fullscreen.png";
+		previewFullscreenButton.src = static_url("fullscreen.png");
 		previewFullscreenButton.alt = "Enter fullscreen";
 	}
 });
 
+previewFullscreenToggle.addEventListener("click", () => fullscreen_toggle());
+
 // slideshow
 
 let slideshow_interval = null;
@@ -176,7 +232,7 @@ function slideshow_start() {
 	}
 	timeout = Math.round(parseFloat(timeout) * 1000);
 	slideshow_interval = setInterval(() => preview_next(), timeout);
-	previewSlideshowButton.src = "/static/slideshow-stop.png";
+	previewSlideshowButton.src = static_url("slideshow-stop.png");
 	previewSlideshowButton.alt = "Stop slideshow";
 }
 
@@ -186,7 +242,7 @@ function slideshow_stop() {
 	}
 	clearInterval(slideshow_interval);
 	slideshow_interval = null;
-	previewSlideshowButton.src = "/static/slideshow-start.png";
+	previewSlideshowButton.src = static_url("slideshow-start.png");
 	previewSlideshowButton.alt = "Start slideshow";
 }
 
@@ -198,6 +254,108 @@ function slideshow_toggle() {
 	}
 }
 
+previewSlideshowToggle.addEventListener("click", () => slideshow_toggle());
+
+// uploads
+
+function upload_files(files) {
+	const data = new FormData();
+	for (const file of files) {
+		data.append("file", file);
+	}
+	uploadStatus.innerText = "Uploading…";
+	fetch(location.pathname, {
+		method: "POST",
+		body: data,
+		headers: { Accept: "application/json" },
+	}).then(async (response) => {
+		if (response.ok) {
+			location.reload();
+		} else {
+			const error = await response.json();
+			uploadStatus.innerText = `Upload failed: ${error.error}`;
+		}
+	});
+}
+
+if (window.uploadForm) {
+	document.addEventListener("dragover", (event) => {
+		if (event.dataTransfer.types.includes("Files")) {
+			event.preventDefault();
+			uploadForm.classList.add("dragging");
+		}
+	});
+	document.addEventListener("dragleave", (event) => {
+		// only when leaving the window rather than moving between elements
+		if (event.relatedTarget === null) {
+			uploadForm.classList.remove("dragging");
+		}
+	});
+	document.addEventListener("drop", (event) => {
+		uploadForm.classList.remove("dragging");
+		if (event.dataTransfer.files.length > 0) {
+			event.preventDefault();
+			upload_files(event.dataTransfer.files);
+		}
+	});
+}
+
+// file management
+
+function manage(url, fields) {
+	fetch(url, {
+		method: "POST",
+		body: new URLSearchParams(fields),
+		headers: { Accept: "application/json" },
+	}).then(async (response) => {
+		if (response.ok) {
+			location.reload();
+		} else {
+			const error = await response.json();
+			alert(`Failed: ${error.error}`);
+		}
+	});
+}
+
+if (window.manageMkdir) {
+	manageMkdir.addEventListener("click", () => {
+		const name = prompt("Name of the new folder:");
+		if (name) {
+			manage(location.pathname, { action: "mkdir", name });
+		}
+	});
+}
+
+entriesList.addEventListener("click", (event) => {
+	const button = event.target.closest("button[data-action]");
+	if (button === null) {
+		return;
+	}
+	const entry = entries[parseInt(button.closest("tr").dataset.entryIdx)];
+	switch (button.dataset.action) {
+		case "rename": {
+			const name = prompt(`New name for ${entry.name}:`, entry.name);
+			if (name && name !== entry.name) {
+				manage(entry.url, { action: "rename", name });
+			}
+			break;
+		}
+		case "move": {
+			const destination = prompt(`Folder to move ${entry.name} into (for example, /photos/2020):`, "/");
+			if (destination) {
+				manage(entry.url, { action: "move", destination });
+			}
+			break;
+		}
+		case "delete": {
+			if (confirm(`Move ${entry.name} to the trash?`)) {
+				manage(entry.url, { action: "delete" });
+			}
+			break;
+		}
+	}
+});
+
 // keyboard shortcuts
 
 document.addEventListener("keydown", (event) => {